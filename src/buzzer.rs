@@ -1,12 +1,54 @@
 use sdl2::Sdl;
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice, AudioStatus};
+use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// How long it takes for the tone to fade in or out, going from silence to
+// full volume in a single sample is heard as a click
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+// Tone played while the sound timer is running (and no XO-CHIP pattern has
+// been loaded), the defaults are what the buzzer always played
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BuzzerConfig {
+    pub frequency: f32, // in Hz
+    pub volume: f32,    // 0.0 to 1.0
+    pub waveform: Waveform,
+}
+
+impl Default for BuzzerConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+// The audio device is kept playing all the time, turning the buzzer on or off
+// only tells the callback which way to ramp its gain
 pub struct Buzzer {
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<Wave>,
+    active: Arc<AtomicBool>,
 }
 
 impl Buzzer {
     pub fn from_sdl_context(sdl_context: &Sdl) -> Self {
+        Self::from_sdl_context_with_config(sdl_context, BuzzerConfig::default())
+    }
+
+    pub fn from_sdl_context_with_config(sdl_context: &Sdl, config: BuzzerConfig) -> Self {
         let audio_subsystem = sdl_context.audio().unwrap();
     
         let desired_spec = AudioSpecDesired {
@@ -15,50 +57,196 @@ impl Buzzer {
             samples: None       // default sample size
         };
         
+        let active = Arc::new(AtomicBool::new(false));
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
             // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
+            Wave::new(config, spec.freq as f32, active.clone())
         }).unwrap();
+        device.resume();
 
-        Buzzer { device }
+        Buzzer { device, active }
     }
 
     pub fn is_on(&self) -> bool {
-        self.device.status() == AudioStatus::Playing
+        self.active.load(Ordering::Relaxed)
     }
 
     pub fn start(&self) {
-        self.device.resume();
+        self.active.store(true, Ordering::Relaxed);
     }
 
     pub fn stop(&self) {
-        self.device.pause();
+        self.active.store(false, Ordering::Relaxed);
+    }
+
+    // Plays the given XO-CHIP pattern (128 1-bit samples, MSB first) in a
+    // loop at `rate` samples per second instead of the configured tone
+    pub fn set_pattern(&mut self, pattern: [u8; 16], rate: f32) {
+        let mut wave = self.device.lock();
+        if wave.pattern != Some(pattern) {
+            wave.pattern = Some(pattern);
+            wave.phase = 0.0;
+        }
+        wave.pattern_phase_inc = rate / wave.sample_rate / 128.0;
+    }
+
+    // Clamped to 0.0..=1.0
+    pub fn set_volume(&mut self, volume: f32) {
+        self.device.lock().volume = volume.clamp(0.0, 1.0);
+    }
+
+    // A muted buzzer keeps playing (and reporting `is_on`) silently
+    pub fn set_muted(&mut self, muted: bool) {
+        self.device.lock().muted = muted;
     }
 }
 
-pub struct SquareWave {
+pub struct Wave {
     phase_inc: f32,
     phase: f32,
-    volume: f32
+    volume: f32,
+    muted: bool,
+    waveform: Waveform,
+    sample_rate: f32,
+    pattern: Option<[u8; 16]>,
+    pattern_phase_inc: f32,
+    active: Arc<AtomicBool>,
+    gain: f32, // current envelope level, from 0.0 to 1.0
+    gain_step: f32,
+}
+
+impl Wave {
+    fn new(config: BuzzerConfig, sample_rate: f32, active: Arc<AtomicBool>) -> Self {
+        Wave {
+            phase_inc: config.frequency / sample_rate,
+            phase: 0.0,
+            volume: config.volume.clamp(0.0, 1.0),
+            muted: false,
+            waveform: config.waveform,
+            sample_rate,
+            pattern: None,
+            pattern_phase_inc: 0.0,
+            active,
+            gain: 0.0,
+            gain_step: 1.0 / (ENVELOPE_SECONDS * sample_rate),
+        }
+    }
+
+    // Moves the gain one sample's worth towards full volume or silence
+    fn next_gain(&mut self, target: f32) -> f32 {
+        if self.gain < target {
+            self.gain = (self.gain + self.gain_step).min(target);
+        } else {
+            self.gain = (self.gain - self.gain_step).max(target);
+        }
+        self.gain
+    }
+
+    // Value of the configured tone at the current phase, within +-volume
+    fn tone_sample(&self) -> f32 {
+        let unit = match self.waveform {
+            Waveform::Square => if self.phase <= 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+        };
+        unit * self.volume
+    }
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Wave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
+        let target = if self.active.load(Ordering::Relaxed) { 1.0 } else { 0.0 };
+        if self.muted || (target == 0.0 && self.gain == 0.0) {
+            out.fill(0.0);
+            return;
+        }
+
+        if let Some(pattern) = self.pattern {
+            // Stream the pattern, one full pass per phase cycle
+            for x in out.iter_mut() {
+                let bit = (self.phase * 128.0) as usize % 128;
+                *x = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    self.volume
+                } else {
+                    -self.volume
+                } * self.next_gain(target);
+                self.phase = (self.phase + self.pattern_phase_inc) % 1.0;
+            }
+            return;
+        }
+
+        // Generate the configured tone
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
+            *x = self.tone_sample() * self.next_gain(target);
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::buzzer::*;
+
+    #[test]
+    fn test_waveforms_stay_within_volume() {
+        for waveform in [Waveform::Square, Waveform::Sine, Waveform::Triangle, Waveform::Sawtooth] {
+            let config = BuzzerConfig { frequency: 440.0, volume: 0.5, waveform };
+            let mut wave = Wave::new(config, 44100.0, Arc::new(AtomicBool::new(true)));
+            let mut out = [0.0; 1024];
+            wave.callback(&mut out);
+
+            assert!(out.iter().all(|x| x.abs() <= 0.5 + f32::EPSILON));
+            // A full period fits in the buffer, so the peaks get reached
+            let max = out.iter().cloned().fold(f32::MIN, f32::max);
+            let min = out.iter().cloned().fold(f32::MAX, f32::min);
+            assert!(max > 0.45 && min < -0.45);
+        }
+    }
+
+    #[test]
+    fn test_silent_at_zero_volume() {
+        let config = BuzzerConfig { volume: 0.0, ..BuzzerConfig::default() };
+        let mut wave = Wave::new(config, 44100.0, Arc::new(AtomicBool::new(true)));
+        let mut out = [1.0; 256];
+        wave.callback(&mut out);
+        assert!(out.iter().all(|x| *x == 0.0));
+
+        let mut wave = Wave::new(BuzzerConfig::default(), 44100.0, Arc::new(AtomicBool::new(true)));
+        wave.muted = true;
+        let mut out = [1.0; 256];
+        wave.callback(&mut out);
+        assert!(out.iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn test_onset_ramps_up() {
+        // A still square wave is a constant, which makes the envelope easy
+        // to look at
+        let config = BuzzerConfig { frequency: 0.0, volume: 0.5, waveform: Waveform::Square };
+        let active = Arc::new(AtomicBool::new(false));
+        let mut wave = Wave::new(config, 44100.0, active.clone());
+        let ramp_len = (ENVELOPE_SECONDS * 44100.0).ceil() as usize;
+
+        let mut out = [1.0; 64];
+        wave.callback(&mut out);
+        assert!(out.iter().all(|x| *x == 0.0));
+
+        active.store(true, Ordering::Relaxed);
+        let mut out = vec![0.0; ramp_len * 2];
+        wave.callback(&mut out);
+        assert!(out[0] > 0.0 && out[0] < 0.01);
+        assert!(out.windows(2).all(|w| w[0] <= w[1]));
+        assert!(out[ramp_len / 2] < 0.5);
+        assert!(out[ramp_len..].iter().all(|x| *x == 0.5));
+
+        active.store(false, Ordering::Relaxed);
+        let mut out = vec![0.0; ramp_len * 2];
+        wave.callback(&mut out);
+        assert!(out[0] > 0.49);
+        assert!(out.windows(2).all(|w| w[0] >= w[1]));
+        assert!(out[ramp_len..].iter().all(|x| *x == 0.0));
+    }
+}
@@ -0,0 +1,259 @@
+use alloc::vec::Vec;
+
+use crate::rip8::{fnv1a, Quirks, Rip8, Rip8Error, StepOutcome, RIP8_KEY_COUNT};
+
+const INPUT_LOG_MAGIC: &[u8] = b"RVI";
+const INPUT_LOG_VERSION: u8 = 1;
+
+// Runs a machine one 60Hz frame at a time, always the same way: the frame's
+// keypad state is applied, the timers tick once and then exactly the same
+// number of cycles is spent. Cycles are instructions, unless the machine was
+// given a `Timing`. Feeding it the same input log (with the same ROM and a
+// seeded random number source) reproduces a run exactly, which is what
+// replays and tool-assisted runs need
+pub struct FrameDriver {
+    rip8: Rip8,
+    cycles_per_frame: usize,
+    frame_count: u64,
+}
+
+impl FrameDriver {
+    pub fn new(rip8: Rip8, cycles_per_frame: usize) -> Self {
+        FrameDriver { rip8, cycles_per_frame, frame_count: 0 }
+    }
+
+    // `keys` has one bit per keypad key, bit 0 being key 0, set for the keys
+    // held down during the frame. Stalled instructions (waiting for a key or
+    // for the next frame) still use up a cycle each
+    pub fn run_frame(&mut self, keys: u16) -> StepOutcome {
+        self.frame_count += 1;
+        Self::drive_frame(&mut self.rip8, keys, self.cycles_per_frame)
+    }
+
+    // What `run_frame` does, for machines owned elsewhere (such as by a
+    // frontend that only drives frames this way some of the time)
+    pub fn drive_frame(rip8: &mut Rip8, keys: u16, cycles_per_frame: usize) -> StepOutcome {
+        for k in 0..RIP8_KEY_COUNT {
+            rip8.set_keydown(k, keys & (1 << k) != 0);
+        }
+        rip8.tick_timers(1.0 / 60.0);
+        let mut cycles = 0;
+        while cycles < cycles_per_frame {
+            let outcome = rip8.step();
+            if outcome != StepOutcome::Continue {
+                return outcome;
+            }
+            cycles += rip8.get_last_step_cost().max(1) as usize;
+        }
+        StepOutcome::Continue
+    }
+
+    // Runs a frame per entry in `input_log`, stopping early if the program
+    // does
+    pub fn run_frames(&mut self, input_log: &[u16]) -> StepOutcome {
+        for keys in input_log {
+            let outcome = self.run_frame(*keys);
+            if outcome != StepOutcome::Continue {
+                return outcome;
+            }
+        }
+        StepOutcome::Continue
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn get_rip8(&self) -> &Rip8 {
+        &self.rip8
+    }
+
+    pub fn get_rip8_mut(&mut self) -> &mut Rip8 {
+        &mut self.rip8
+    }
+
+    pub fn into_rip8(self) -> Rip8 {
+        self.rip8
+    }
+}
+
+// To tell ROMs apart without keeping them around
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom)
+}
+
+// Runs `rom` with `quirks` and otherwise default settings for `cycles`
+// instructions, letting time go by at the default frequency, and returns the
+// first display plane (see `Rip8::framebuffer`). Test ROMs end up looping
+// forever over a screen showing their results, which is how they get checked
+pub fn run_rom_to_framebuffer(rom: &[u8], quirks: Quirks, cycles: usize) -> Result<Vec<u8>, Rip8Error> {
+    let mut rip8 = Rip8::builder().rom(rom).quirks(quirks).build()?;
+    rip8.run_until_halt(cycles);
+    Ok(rip8.framebuffer().to_vec())
+}
+
+// Stable across platforms, to compare screens against a recorded value
+pub fn framebuffer_hash(framebuffer: &[u8]) -> u64 {
+    fnv1a(framebuffer)
+}
+
+// Everything needed to replay a run through `FrameDriver`: the random seed,
+// the ROM it was recorded on and the keypad state of every frame. Options
+// such as quirks, the memory model or the timing aren't recorded, they have
+// to match too
+//
+// File format, all multi-byte values are big endian:
+//   magic "RVI", version (1 byte)
+//   seed (8), rom hash (8, see `rom_hash`), cycles per frame (4)
+//   frame count (4), keypad state of each frame (2 per frame, bit k for key k)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub seed: u64,
+    pub rom_hash: u64,
+    pub cycles_per_frame: u32,
+    pub frames: Vec<u16>,
+}
+
+impl InputLog {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(28 + self.frames.len() * 2);
+        bytes.extend_from_slice(INPUT_LOG_MAGIC);
+        bytes.push(INPUT_LOG_VERSION);
+        bytes.extend_from_slice(&self.seed.to_be_bytes());
+        bytes.extend_from_slice(&self.rom_hash.to_be_bytes());
+        bytes.extend_from_slice(&self.cycles_per_frame.to_be_bytes());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for keys in &self.frames {
+            bytes.extend_from_slice(&keys.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Rip8Error> {
+        if !bytes.starts_with(INPUT_LOG_MAGIC) {
+            return Err(Rip8Error::InputLogBadMagic);
+        }
+        let mut rest = &bytes[INPUT_LOG_MAGIC.len()..];
+        let mut take = |len: usize| -> Result<&[u8], Rip8Error> {
+            if rest.len() < len {
+                return Err(Rip8Error::InputLogTruncated);
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Ok(taken)
+        };
+
+        let version = take(1)?[0];
+        if version != INPUT_LOG_VERSION {
+            return Err(Rip8Error::InputLogVersion(version));
+        }
+        let seed = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        let rom_hash = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        let cycles_per_frame = u32::from_be_bytes(take(4)?.try_into().unwrap());
+        let frame_count = u32::from_be_bytes(take(4)?.try_into().unwrap()) as usize;
+        let frames = take(frame_count.saturating_mul(2))?
+            .chunks_exact(2)
+            .map(|keys| u16::from_be_bytes([keys[0], keys[1]]))
+            .collect();
+        Ok(InputLog { seed, rom_hash, cycles_per_frame, frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::*;
+    use crate::rip8::Timing;
+    use alloc::vec;
+
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn seeded_driver(seed: u64) -> FrameDriver {
+        let rom = vec![
+            0x65, 0x05, // v5 = 5
+            0xc0, 0x3f, // v0 = rand & 0x3f
+            0xc1, 0x1f, // v1 = rand & 0x1f
+            0xf2, 0x29, // i = font(v2)
+            0xd0, 0x15, // draw i..i[5] at (v0, v1)
+            0xe5, 0x9e, // skip if key v5 is down
+            0x72, 0x01, // v2 += 1
+            0x12, 0x02, // jp 0x202
+        ];
+        let mut rng = StdRng::seed_from_u64(seed);
+        FrameDriver::new(Rip8::from_rom(&rom, 480, move || -> u8 { rng.gen::<u8>() }), 8)
+    }
+
+    #[test]
+    fn test_replays_are_identical() {
+        let input_log: Vec<u16> = (0..120).map(|frame| if frame % 7 < 3 { 1 << 5 } else { 0 }).collect();
+
+        let mut first = seeded_driver(42);
+        let mut second = seeded_driver(42);
+        assert_eq!(first.run_frames(&input_log), StepOutcome::Continue);
+        assert_eq!(second.run_frames(&input_log), StepOutcome::Continue);
+        assert_eq!(first.get_frame_count(), 120);
+        assert_eq!(first.get_rip8().get_cycle_count(), 120 * 8);
+        assert_eq!(first.get_rip8().framebuffer(), second.get_rip8().framebuffer());
+        assert!(!first.get_rip8().lit_pixels().is_empty());
+
+        // The input does make a difference
+        let mut third = seeded_driver(42);
+        third.run_frames(&vec![0; 120]);
+        assert_ne!(first.get_rip8().get_register(2), third.get_rip8().get_register(2));
+    }
+
+    #[test]
+    fn test_frame_cycle_budget() {
+        let rom = [
+            0x70, 0x01, // v0 += 1
+            0x12, 0x00, // jp 0x200
+        ];
+
+        // Each pass through the loop costs 100 + 110 VIP machine cycles
+        let rip8 = Rip8::builder().rom(&rom).timing(Timing::cosmac_vip()).build().unwrap();
+        let mut driver = FrameDriver::new(rip8, 2100);
+        assert_eq!(driver.run_frames(&[0; 3]), StepOutcome::Continue);
+        assert_eq!(driver.get_rip8().get_cycle_count(), 3 * 20);
+        assert_eq!(driver.get_rip8().get_register(0), 0xff_u8.wrapping_add(3 * 10));
+    }
+
+    #[test]
+    fn test_input_log_round_trip() {
+        let log = InputLog {
+            seed: 0x0123456789abcdef,
+            rom_hash: rom_hash(&[0x12, 0x00]),
+            cycles_per_frame: 9,
+            frames: vec![0x0000, 0x0020, 0x8001, 0xffff],
+        };
+        let bytes = log.serialize();
+        assert_eq!(InputLog::deserialize(&bytes), Ok(log.clone()));
+
+        assert_eq!(InputLog::deserialize(b"RIP8"), Err(Rip8Error::InputLogBadMagic));
+        assert_eq!(InputLog::deserialize(&bytes[..bytes.len() - 1]), Err(Rip8Error::InputLogTruncated));
+        let mut future = bytes.clone();
+        future[3] = 99;
+        assert_eq!(InputLog::deserialize(&future), Err(Rip8Error::InputLogVersion(99)));
+
+        assert_ne!(rom_hash(&[0x12, 0x00]), rom_hash(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn test_run_rom_to_framebuffer() {
+        let rom = [
+            0x60, 0x05, // v0 = 5
+            0xf0, 0x29, // i = digit sprite for v0
+            0xd0, 0x05, // draw it at (5, 5)
+            0x12, 0x06, // loop forever
+        ];
+
+        let framebuffer = run_rom_to_framebuffer(&rom, Quirks::default(), 100).unwrap();
+        let mut rip8 = Rip8::from_rom(&rom, 540, || -> u8 { 0 });
+        let blank = framebuffer_hash(rip8.framebuffer());
+        rip8.run_cycles(3);
+        assert_eq!(framebuffer, rip8.framebuffer());
+        assert_eq!(framebuffer_hash(&framebuffer), framebuffer_hash(rip8.framebuffer()));
+        assert_ne!(framebuffer_hash(&framebuffer), blank);
+
+        assert_eq!(run_rom_to_framebuffer(&[0x00; 0x1000], Quirks::default(), 100), Err(Rip8Error::RomTooLarge(0x1000)));
+    }
+}
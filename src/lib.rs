@@ -0,0 +1,14 @@
+// The interpreter core, free of any frontend (and SDL) dependencies. The
+// SDL frontend lives in the `rip8` binary, behind the default `sdl` feature.
+// With the `std` feature off the core builds as `no_std` + `alloc`
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+mod rip8;
+mod driver;
+
+pub use crate::rip8::*;
+pub use crate::driver::*;
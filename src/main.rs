@@ -1,19 +1,29 @@
 extern crate sdl2;
 
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::BufWriter;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use sdl2::pixels::Color;
-use sdl2::event::Event;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::mouse::MouseButton;
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 
 use clap::Parser;
 
-pub mod rip8;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
 pub mod buzzer;
+pub mod recorder;
 
 use rip8::*;
 use buzzer::*;
+use recorder::*;
 
 const SCANCODE_MAPPING: [Scancode; RIP8_KEY_COUNT] = [
     Scancode::X,
@@ -24,18 +34,56 @@ const SCANCODE_MAPPING: [Scancode; RIP8_KEY_COUNT] = [
     Scancode::Num4,Scancode::R,Scancode::F,Scancode::V
 ];
 
+// The virtual keypad takes this fraction (1/N) of the window's height, the
+// window grows by half its height to make room for it
+const VIRTUAL_KEYPAD_SHARE: u32 = 3;
+
+// Layout of the COSMAC VIP hex keypad, row by row
+const KEYPAD_LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xc],
+    [0x4, 0x5, 0x6, 0xd],
+    [0x7, 0x8, 0x9, 0xe],
+    [0xa, 0x0, 0xb, 0xf],
+];
+
+// CDP1802 machine cycles the VIP interpreter gets per 60Hz display refresh,
+// about 3668 minus what the display DMA and its interrupt take. Frames are
+// budgeted in these under `--authentic-speed`, along with `Timing::cosmac_vip`,
+// whether played, recorded or replayed
+const VIP_MACHINE_CYCLES_PER_TICK: u32 = 2600;
+
+// Range the frequency can be moved within at runtime, and how coarse those
+// changes are (multiples of 60 keep timers and instructions in step)
+const MIN_FREQUENCY: u32 = 60;
+const MAX_FREQUENCY: u32 = 60_000;
+
+// How many single steps taken while paused can be undone. Rewind history is
+// only kept while paused, so running at full speed doesn't pay for it
+const STEP_BACK_DEPTH: usize = 64;
+
+// How many 60Hz ticks a recorded or replayed run may catch up on in a single
+// host frame, after a hiccup the rest are dropped rather than rushed through
+const MAX_TICKS_PER_FRAME: f64 = 4.0;
+
+fn frequency_step(frequency: u32) -> u32 {
+    (frequency / 10 / 60).max(1) * 60
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     #[arg()]
     file: String,
 
-    #[arg(short='i', long="image", default_value_t=false, help="Load FILE as a complete Rip8 image (must be 4096 bytes)")]
+    #[arg(short='i', long="image", default_value_t=false, help="Load FILE as a complete Rip8 image (4096 bytes, or 65536 for XO-CHIP)")]
     is_image: bool,
 
     #[arg(short, long, default_value_t=540)]
     freq: u32,
 
+    #[arg(long, default_value_t=false, conflicts_with="freq", help="Approximate the original COSMAC VIP speed instead of using a fixed frequency")]
+    authentic_speed: bool,
+
     #[arg(short, long, default_value_t=0x200, help="Loading/start address")]
     address: u16,
 
@@ -45,14 +93,357 @@ struct Args {
     #[arg(long, default_value_t=400, help="Window height")]
     height: u32,
 
-    #[arg(short, default_value_t=false, help="S-CHIP semantics (affects shift, load/store instructions)")]
+    #[arg(long, default_value_t=false, help="Give the ROM XO-CHIP's 64KB of memory instead of 4KB (images imply it by their size)")]
+    xo_chip: bool,
+
+    #[arg(short, default_value_t=false, help="S-CHIP semantics (affects shift, jump with offset, draw and load/store instructions)")]
     s_chip: bool,
+
+    #[arg(long, default_value_t=false, help="Start with V0-VF and I set to 0 rather than 0xFF, like most interpreters")]
+    zero_registers: bool,
+
+    #[arg(long, default_value_t=false, help="Show the keypad overlay on startup (toggle with F1)")]
+    show_keys: bool,
+
+    #[arg(long, default_value_t=false, help="Show a keypad below the display that can be clicked with the mouse (or tapped)")]
+    virtual_keypad: bool,
+
+    #[arg(long="map", value_name="KEY=SCANCODE", help="Bind a keypad key (0-F) to a keyboard key, by its SDL scancode name (e.g. --map 5=Space), can be repeated")]
+    key_maps: Vec<String>,
+
+    #[arg(long, value_name="OUT.GIF", help="Record the display into an animated GIF (stop with F9)")]
+    record: Option<String>,
+
+    #[arg(long, help="Seed for the random number generator, so that runs can be reproduced")]
+    seed: Option<u64>,
+
+    #[arg(long, value_name="LOG.RVI", conflicts_with="replay_input", help="Record the keypad state of every frame into LOG.RVI, to be replayed with --replay-input")]
+    record_input: Option<String>,
+
+    #[arg(long, value_name="LOG.RVI", conflicts_with_all=["seed", "freq"], help="Replay a run recorded with --record-input, the keyboard is ignored. Options such as --authentic-speed have to match the recording's")]
+    replay_input: Option<String>,
+
+    #[arg(long, value_name="FILE.SYM", help="Label addresses when printing instructions, FILE.SYM lists an address (in hex) and a name per line")]
+    symbols: Option<String>,
+
+    #[arg(long, value_name="ADDR", value_parser=parse_address, help="Pause when about to execute the instruction at ADDR (decimal, or hex like 0x200), can be repeated")]
+    break_at: Vec<u16>,
+
+    #[arg(long, default_value_t=false, help="Pause before executing the first instruction")]
+    break_at_start: bool,
+
+    #[arg(long, default_value_t=false, help="Stop if the program writes below 0x200, where the font lives")]
+    protect_memory: bool,
+
+    #[arg(long, default_value_t=false, help="Scan the ROM before running it and print what it seems to use")]
+    analyze: bool,
+
+    #[arg(long, default_value_t=false, help="Print a disassembly of the ROM, from its load address to its end, and exit")]
+    disasm: bool,
+
+    #[arg(long, value_name="FILE", help="Save the memory to FILE if the program stops on an error, it can be loaded back with -i")]
+    core_dump: Option<String>,
+
+    #[arg(long, default_value_t=false, help="Count the instructions run by kind and print the counts on exit")]
+    profile: bool,
+
+    #[arg(long, default_value_t=false, help="Show the effective instructions per second on the window title")]
+    show_speed: bool,
+
+    #[arg(long, default_value_t=false, help="Run fullscreen, on the desktop's resolution")]
+    fullscreen: bool,
+
+    #[arg(long, default_value_t=false, help="Scale the display by a whole factor, centering it, rather than stretching it")]
+    integer_scale: bool,
+
+    #[arg(long, visible_alias="color1", default_value="00FF00", value_name="RRGGBB", value_parser=parse_color, help="Color of lit spots (on the first plane only, for XO-CHIP)")]
+    fg: Color,
+
+    #[arg(long, visible_alias="color0", default_value="000000", value_name="RRGGBB", value_parser=parse_color, help="Color of unlit spots")]
+    bg: Color,
+
+    #[arg(long, default_value="FF6600", value_name="RRGGBB", value_parser=parse_color, help="Color of spots lit on XO-CHIP's second plane only")]
+    color2: Color,
+
+    #[arg(long, default_value="FFFFFF", value_name="RRGGBB", value_parser=parse_color, help="Color of spots lit on both XO-CHIP planes")]
+    color3: Color,
+
+    #[arg(long, default_value="000000", value_name="RRGGBB", value_parser=parse_color, help="Color of the window around the display")]
+    border: Color,
+
+    #[arg(long, default_value_t=false, help="Outline every spot with a thin dark line")]
+    grid: bool,
+
+    #[arg(long, default_value_t=false, help="Darken every other row of the output, like a CRT")]
+    scanlines: bool,
+
+    #[arg(long, default_value_t=0, value_name="FRAMES", help="Fade spots out over FRAMES frames after they're turned off, to reduce flicker (0 disables it)")]
+    ghosting: u32,
+
+    #[arg(long, default_value_t=0.25, help="Buzzer volume, from 0.0 to 1.0 (mute/unmute with F2)")]
+    volume: f32,
+
+    #[arg(long, default_value_t=4096, help="Maximum instructions executed per frame, excess cycles are dropped")]
+    max_cycles_per_frame: u32,
+
+    #[arg(long, default_value_t=false, help="Start in turbo mode, running --max-cycles-per-frame instructions every frame (toggle with Tab)")]
+    turbo: bool,
+}
+
+fn parse_color(hex: &str) -> Result<Color, String> {
+    let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' is not a RRGGBB hex color", hex));
+    }
+    let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap();
+    Ok(Color::RGB(channel(0), channel(2), channel(4)))
+}
+
+fn to_rgba(color: Color) -> [u8; 4] {
+    [color.r, color.g, color.b, 0xff]
+}
+
+// Takes an address in decimal or 0x-prefixed hex
+fn parse_address(text: &str) -> Result<u16, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not an address", text))
+}
+
+// Applies the user's `KEY=SCANCODE` bindings on top of the default layout
+fn parse_key_mapping(key_maps: &[String]) -> Result<[Scancode; RIP8_KEY_COUNT], String> {
+    let mut mapping = SCANCODE_MAPPING;
+    let mut remapped = [false; RIP8_KEY_COUNT];
+    for key_map in key_maps {
+        let (key, name) = match key_map.split_once('=') {
+            Some(parts) => parts,
+            None => return Err(format!("invalid mapping '{}', expected KEY=SCANCODE", key_map)),
+        };
+        let key = match usize::from_str_radix(key.trim(), 16) {
+            Ok(key) if key < RIP8_KEY_COUNT => key,
+            _ => return Err(format!("invalid key '{}' in mapping '{}', expected 0-F", key, key_map)),
+        };
+        let scancode = match Scancode::from_name(name.trim()) {
+            Some(scancode) => scancode,
+            None => return Err(format!("unknown scancode '{}' in mapping '{}'", name, key_map)),
+        };
+        if remapped[key] {
+            return Err(format!("key {:X} is mapped more than once", key));
+        }
+        remapped[key] = true;
+        mapping[key] = scancode;
+    }
+
+    // A remapped key may now collide with a default binding
+    for k in 0..RIP8_KEY_COUNT {
+        for other in (k + 1)..RIP8_KEY_COUNT {
+            if mapping[k] == mapping[other] {
+                return Err(format!("keys {:X} and {:X} are both bound to {}", k, other, mapping[k].name()));
+            }
+        }
+    }
+    Ok(mapping)
+}
+
+// Saves the display as a PNG on the working directory, using the same colors
+// and spot size as the window
+fn save_screenshot(rip8: &Rip8, scale: usize, palette: &[Color; 4]) -> Result<String, String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path = format!("rip8-{}.png", timestamp);
+    let (width, height, rgba) = rip8.to_rgba(&palette.map(to_rgba), scale.max(1));
+
+    let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgba).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+fn finish_recording(recorder: GifRecorder) {
+    match recorder.finish() {
+        Ok(()) => println!("Recording saved"),
+        Err(e) => println!("Could not finish recording: {}", e),
+    }
+}
+
+// Where a `columns` x `rows` display goes within a `width` x `height` output:
+// returns the offset of its top left corner and the size of each spot.
+// Stretching fills the whole output (as far as whole spot sizes allow),
+// integer scaling keeps spots square and centers the display
+fn display_layout(width: u32, height: u32, columns: usize, rows: usize, integer_scale: bool) -> (i32, i32, u32, u32) {
+    let spot_width = (width / columns as u32).max(1);
+    let spot_height = (height / rows as u32).max(1);
+    if !integer_scale {
+        return (0, 0, spot_width, spot_height);
+    }
+
+    let spot_size = spot_width.min(spot_height);
+    let offset_x = width.saturating_sub(spot_size * columns as u32) / 2;
+    let offset_y = height.saturating_sub(spot_size * rows as u32) / 2;
+    (offset_x as i32, offset_y as i32, spot_size, spot_size)
+}
+
+// Splits a `width` x `height` output between the display, on top, and the
+// virtual keypad below it (if shown). Returns the height left for the
+// display and the keypad's area
+fn virtual_keypad_layout(width: u32, height: u32, virtual_keypad: bool) -> (u32, Option<Rect>) {
+    if !virtual_keypad {
+        return (height, None);
+    }
+    let keypad_height = height / VIRTUAL_KEYPAD_SHARE;
+    let display_height = height - keypad_height;
+    (display_height, Some(Rect::new(0, display_height as i32, width.max(1), keypad_height.max(1))))
+}
+
+// Which key of a keypad drawn on `area` is under the given point, if any
+fn keypad_key_at(area: Rect, x: i32, y: i32) -> Option<usize> {
+    if !area.contains_point((x, y)) {
+        return None;
+    }
+    let col = ((x - area.x()) as u32 * 4 / area.width()).min(3) as usize;
+    let row = ((y - area.y()) as u32 * 4 / area.height()).min(3) as usize;
+    Some(KEYPAD_LAYOUT[row][col])
+}
+
+fn print_rom_report(report: &RomReport, s_chip: bool) {
+    println!("ROM analysis (data is decoded as code too, so expect false positives):");
+    println!("  {} instructions, {} unknown", report.instruction_count, report.unknown_opcodes.len());
+    for (addr, ir) in report.unknown_opcodes.iter().take(8) {
+        println!("    0x{:03X}: {:04X}", addr, ir);
+    }
+    if report.unknown_opcodes.len() > 8 {
+        println!("    ...");
+    }
+    match report.highest_register {
+        Some(r) => println!("  Registers used up to V{:X}", r),
+        None => println!("  No registers used"),
+    }
+    if report.uses_s_chip {
+        println!("  Uses S-CHIP instructions{}", if s_chip { "" } else { ", consider running with -s" });
+    }
+    if report.uses_xo_chip {
+        println!("  Uses XO-CHIP instructions");
+    }
+}
+
+// One `ADDR: OPCODE  MNEMONIC` line per instruction of `memory` from
+// `start` to its end, long loads show the address following F000 too
+fn print_disassembly(memory: &[u8], start: u16, symbols: &BTreeMap<u16, String>) {
+    let listing = disassemble_range_with_symbols(memory, start, memory.len(), symbols);
+    for (idx, (addr, text)) in listing.iter().enumerate() {
+        let end = listing.get(idx + 1).map_or(memory.len(), |(next, _)| *next as usize);
+        let opcode: Vec<String> = memory[*addr as usize..end].chunks(2)
+            .map(|word| word.iter().map(|b| format!("{:02X}", b)).collect())
+            .collect();
+        println!("0x{:03X}: {:<9}  {}", addr, opcode.join(" "), text);
+    }
+}
+
+fn print_key_bindings(mapping: &[Scancode; RIP8_KEY_COUNT]) {
+    for row in KEYPAD_LAYOUT {
+        let bindings: Vec<String> = row.iter()
+            .map(|k| format!("{:X} = {:<6}", k, mapping[*k].name()))
+            .collect();
+        println!("{}", bindings.join(" "));
+    }
+}
+
+// Shade drawn over the display by the grid and scanlines
+const CRT_OVERLAY_COLOR: Color = Color::RGBA(0x00, 0x00, 0x00, 0x80);
+
+// Darkens the display drawn on `area` (made of `columns` x `rows` spots)
+// with a line along the edges of each spot and/or on every other output
+// row. The grid is skipped when spots are too small for it to leave
+// anything of them visible
+fn draw_crt_overlay(canvas: &mut sdl2::render::WindowCanvas, area: Rect, columns: usize, rows: usize, grid: bool, scanlines: bool) {
+    let spot_width = area.width() / columns as u32;
+    let spot_height = area.height() / rows as u32;
+    let mut lines = Vec::new();
+    if grid && spot_width >= 3 && spot_height >= 3 {
+        for col in 0..columns as u32 {
+            lines.push(Rect::new(area.x() + (col * spot_width) as i32, area.y(), 1, area.height()));
+        }
+        for row in 0..rows as u32 {
+            lines.push(Rect::new(area.x(), area.y() + (row * spot_height) as i32, area.width(), 1));
+        }
+        lines.push(Rect::new(area.right() - 1, area.y(), 1, area.height()));
+        lines.push(Rect::new(area.x(), area.bottom() - 1, area.width(), 1));
+    }
+    if scanlines {
+        for y in (1..area.height()).step_by(2) {
+            lines.push(Rect::new(area.x(), area.y() + y as i32, area.width(), 1));
+        }
+    }
+    canvas.set_draw_color(CRT_OVERLAY_COLOR);
+    let _ = canvas.fill_rects(&lines);
+}
+
+// Most frequent instructions first
+fn print_opcode_histogram(rip8: &Rip8) {
+    let mut histogram: Vec<(&str, u64)> = rip8.opcode_histogram().into_iter().collect();
+    histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let total: u64 = histogram.iter().map(|(_, count)| count).sum();
+    println!("Instructions run: {}", total);
+    for (pattern, count) in histogram {
+        println!("  {:<8} {:>12} {:>6.2}%", pattern, count, count as f64 * 100.0 / total as f64);
+    }
+}
+
+// Draws the hex keypad on the lower right corner of the screen
+fn draw_keypad_overlay(canvas: &mut sdl2::render::WindowCanvas, pressed: &[bool; RIP8_KEY_COUNT], width: u32, height: u32) {
+    let cell_size = (width.min(height) / 10).max(8);
+    let origin_x = width.saturating_sub(cell_size * 4) as i32;
+    let origin_y = height.saturating_sub(cell_size * 4) as i32;
+    draw_keypad(canvas, pressed, Rect::new(origin_x, origin_y, cell_size * 4, cell_size * 4));
+}
+
+// Draws the hex keypad filling `area`, using the font sprites for the key
+// labels and highlighting the keys being pressed
+fn draw_keypad(canvas: &mut sdl2::render::WindowCanvas, pressed: &[bool; RIP8_KEY_COUNT], area: Rect) {
+    let cell_width = (area.width() / 4).max(2);
+    let cell_height = (area.height() / 4).max(2);
+    let spot_size = (cell_width.min(cell_height) / 8).max(1);
+
+    for (row, keys) in KEYPAD_LAYOUT.iter().enumerate() {
+        for (col, k) in keys.iter().enumerate() {
+            let cell_x = area.x() + (col as u32 * cell_width) as i32;
+            let cell_y = area.y() + (row as u32 * cell_height) as i32;
+            canvas.set_draw_color(if pressed[*k] { Color::RGB(0x60, 0x60, 0x60) } else { Color::RGB(0x20, 0x20, 0x20) });
+            let _ = canvas.fill_rect(Rect::new(cell_x, cell_y, cell_width - 1, cell_height - 1));
+
+            // Font sprites are 4x5 spots, center them within the cell
+            canvas.set_draw_color(Color::WHITE);
+            let label_x = cell_x + (cell_width.saturating_sub(spot_size * 4) / 2) as i32;
+            let label_y = cell_y + (cell_height.saturating_sub(spot_size * 5) / 2) as i32;
+            for (y, byte) in RIP8_FONT[k * 5..k * 5 + 5].iter().enumerate() {
+                for x in 0..4 {
+                    if byte & (0x80 >> x) != 0 {
+                        let _ = canvas.fill_rect(Rect::new(
+                            label_x + (x * spot_size) as i32, label_y + (y as u32 * spot_size) as i32,
+                            spot_size, spot_size));
+                    }
+                }
+            }
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    if args.width != args.height * 2 {
+    let key_mapping = match parse_key_mapping(&args.key_maps) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            println!("Bad key mapping: {}, aborting!", e);
+            std::process::exit(-1);
+        }
+    };
+
+    if args.width != args.height * 2 && !args.integer_scale && !args.fullscreen {
         println!("Running in an aspect ratio other than 2:1, display may look stretched!");
     }
 
@@ -65,93 +456,478 @@ fn main() {
         }
     };
 
-    let frequency = args.freq;
+    if args.analyze && !args.is_image {
+        print_rom_report(&analyze_rom(&rom, args.address), args.s_chip);
+    }
 
-    let mut rip8 = (if args.is_image {
-        Rip8::from_image_at_start
+    let mut frequency = args.freq;
+
+    // Replays bring their own seed and speed, and recordings need a seed to
+    // write down even if none was given
+    let mut replay = match &args.replay_input {
+        Some(path) => match fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| InputLog::deserialize(&bytes).map_err(|e| e.to_string())) {
+            Ok(log) if log.rom_hash != rom_hash(&rom) => {
+                println!("Input log {} was recorded on a different ROM, aborting!", path);
+                std::process::exit(-1);
+            },
+            Ok(log) => Some((log, 0)), // along with the next frame to replay
+            Err(e) => {
+                println!("Could not read input log {}: {}, aborting!", path, e);
+                std::process::exit(-1);
+            }
+        },
+        None => None,
+    };
+    let symbols = match &args.symbols {
+        Some(path) => match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| parse_symbols(&text).map_err(|e| e.to_string())) {
+            Ok(symbols) => symbols,
+            Err(e) => {
+                println!("Could not read symbol file {}: {}, aborting!", path, e);
+                std::process::exit(-1);
+            }
+        },
+        None => BTreeMap::new(),
+    };
+    let seed = match (&replay, args.seed) {
+        (Some((log, _)), _) => Some(log.seed),
+        (None, Some(seed)) => Some(seed),
+        (None, None) if args.record_input.is_some() => Some(StdRng::from_entropy().gen()),
+        (None, None) => None,
+    };
+    // Cycles spent per 60Hz tick, which are instructions unless each one is
+    // given its own cost (as under `--authentic-speed`)
+    let cycles_per_tick = match &replay {
+        Some((log, _)) => log.cycles_per_frame,
+        None if args.authentic_speed => VIP_MACHINE_CYCLES_PER_TICK,
+        None => (frequency / 60).max(1),
+    };
+    let mut recording_log = args.record_input.as_ref().map(|_| InputLog {
+        seed: seed.unwrap_or(0),
+        rom_hash: rom_hash(&rom),
+        cycles_per_frame: cycles_per_tick,
+        frames: Vec::new(),
+    });
+    // Recording or replaying input runs exactly the same instructions every
+    // frame (see `FrameDriver`), which turbo mode, single steps and changing
+    // the frequency would break, so those are disabled
+    let frame_driven = replay.is_some() || recording_log.is_some();
+
+    let memory_model = if args.xo_chip { MemoryModel::XoChip } else { MemoryModel::Chip8 };
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let get_random = move || -> u8 { rng.gen::<u8>() };
+    let builder = if args.is_image {
+        Rip8::builder().image(&rom)
     } else {
-        Rip8::from_rom_at_address
-    })(&rom, frequency, args.address, || -> u8{ rand::random::<u8>() });
+        Rip8::builder().rom(&rom).memory_model(memory_model)
+    };
+    let quirks = if args.s_chip { Quirks::schip() } else { Quirks::default() };
+    let loaded = builder
+        .load_address(args.address)
+        .freq(frequency)
+        .quirks(Quirks { zero_registers: args.zero_registers, ..quirks })
+        .random(get_random)
+        .build();
+    let mut rip8 = match loaded {
+        Ok(rip8) => rip8,
+        Err(e) => {
+            println!("Could not load {}: {}, aborting!", args.file, e);
+            std::process::exit(-1);
+        }
+    };
 
-    rip8.set_s_chip_mode(args.s_chip);
+    if args.disasm {
+        let end = if args.is_image { rom.len() } else { args.address as usize + rom.len() };
+        print_disassembly(&rip8.dump_image()[..end], args.address, &symbols);
+        return;
+    }
+
+    rip8.set_profiling(args.profile);
+    rip8.set_memory_protection(args.protect_memory);
+    for addr in &args.break_at {
+        rip8.add_breakpoint(*addr);
+    }
+    if args.break_at_start {
+        rip8.add_breakpoint(rip8.get_pc());
+    }
 
     // Init SDL2, get a window and a buzzer
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    let window = video_subsystem.window("Rip8", args.width, args.height)
-        .position_centered()
-        .build()
-        .unwrap();
+    let window_height = if args.virtual_keypad { args.height + args.height / 2 } else { args.height };
+    let mut window_builder = video_subsystem.window("Rip8", args.width, window_height);
+    window_builder.position_centered();
+    if args.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
 
     let idx = window.display_index().unwrap();
     let refresh_rate = video_subsystem.current_display_mode(idx).unwrap().refresh_rate as u32;
 
     let mut canvas = window.into_canvas().present_vsync().accelerated().build().unwrap();
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(args.border);
     canvas.clear();
     canvas.present();
 
+    // The display is drawn into a texture at its native resolution, which
+    // the renderer then scales to the window in one go
+    let texture_creator = canvas.texture_creator();
+    let (mut texture_width, mut texture_height) = rip8.framebuffer_dimensions();
+    rip8.take_resolution_changed();
+    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, texture_width as u32, texture_height as u32).unwrap();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let buzzer = Buzzer::from_sdl_context(&sdl_context);
+    let mut buzzer = Buzzer::from_sdl_context(&sdl_context);
+    buzzer.set_volume(args.volume);
+    let mut muted = false;
 
     // Main loop
     let mut running = true;
-    let cycles_per_frame: f32 = args.freq as f32 / refresh_rate as f32;
+    // Cycles are instructions, unless each instruction is given its own cost
+    let mut cycles_per_frame: f32 = if args.authentic_speed {
+        rip8.set_timing(Timing::cosmac_vip());
+        cycles_per_tick as f32 * 60.0 / refresh_rate as f32
+    } else {
+        frequency as f32 / refresh_rate as f32
+    };
     let mut cycles_due: f32 = 0.0;
+    // Driven frames are 60Hz ticks whatever the display's refresh rate,
+    // they're paced by the wall clock rather than by presenting
+    let mut ticks_due: f64 = 0.0;
+    let mut last_tick_check = Instant::now();
+    let mut cycles_dropped = false;
+    let mut show_keys = args.show_keys;
+    if show_keys {
+        print_key_bindings(&key_mapping);
+    }
+    let mut paused = false;
+    let mut turbo = args.turbo;
+    let mut clicked_key: Option<usize> = None; // held down on the virtual keypad
+    let mut speed_check = Instant::now();
+    let mut speed_check_cycles = rip8.get_cycle_count();
+    // Brightness of each spot, lit spots are at 1.0 and fade out from there
+    // once turned off (instantly, unless ghosting is enabled). Spots fade
+    // from the color they had when last lit, which is kept in `planes`. Both
+    // follow the texture's size and are reallocated along with it
+    let mut intensity = vec![0.0f32; texture_width * texture_height];
+    let mut planes = vec![0u8; texture_width * texture_height];
+    // Indexed by the value of a spot on each plane (see `get_display_pixel`)
+    let palette = [args.bg, args.fg, args.color2, args.color3];
+    let decay = if args.ghosting > 0 { 1.0 / args.ghosting as f32 } else { 1.0 };
+    let spot_scale = (args.width as usize / RIP8_DISPLAY_WIDTH).min(args.height as usize / RIP8_DISPLAY_HEIGHT);
+    let mut recorder = match &args.record {
+        Some(path) => match GifRecorder::create(path, &rip8, spot_scale.max(1), palette.map(to_rgba)) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                println!("Could not start recording to {}: {}, aborting!", path, e);
+                std::process::exit(-1);
+            }
+        },
+        None => None,
+    };
+    let frame_duration = Duration::from_secs_f64(1.0 / refresh_rate as f64);
     while running {
-        // Clear screen and handle exit event
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
+        // Handle exit event
+        let mut redraw = show_keys || args.virtual_keypad;
+        let mut step_once = false;
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     running = false
                 },
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    show_keys = !show_keys;
+                    redraw = true;
+                    if show_keys {
+                        print_key_bindings(&key_mapping);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                    muted = !muted;
+                    buzzer.set_muted(muted);
+                },
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => {
+                    paused = !paused;
+                    rip8.enable_delta_rewind(if paused { STEP_BACK_DEPTH } else { 0 });
+                    if paused {
+                        println!("Paused, press P to resume, N to step a single instruction or B to undo one");
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::N), .. } if paused && !frame_driven => {
+                    step_once = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::B), .. } if paused && !frame_driven => {
+                    if rip8.step_back() {
+                        println!("Stepped back to 0x{:03X}", rip8.get_pc());
+                    } else {
+                        println!("Nothing left to step back");
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    // Hires spots are half the size, so screenshots keep the
+                    // same dimensions
+                    match save_screenshot(&rip8, spot_scale * RIP8_DISPLAY_WIDTH / texture_width, &palette) {
+                        Ok(path) => println!("Screenshot saved to {}", path),
+                        Err(e) => println!("Could not save screenshot: {}", e),
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode @ (Keycode::Equals | Keycode::KpPlus | Keycode::Minus | Keycode::KpMinus)), .. } if !args.authentic_speed && !frame_driven => {
+                    frequency = if keycode == Keycode::Equals || keycode == Keycode::KpPlus {
+                        (frequency + frequency_step(frequency)).min(MAX_FREQUENCY)
+                    } else {
+                        frequency.saturating_sub(frequency_step(frequency)).max(MIN_FREQUENCY)
+                    };
+                    cycles_per_frame = frequency as f32 / refresh_rate as f32;
+                    let _ = canvas.window_mut().set_title(&format!("Rip8 - {} Hz", frequency));
+                },
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } if !frame_driven => {
+                    turbo = !turbo;
+                    let title = if turbo {
+                        "Rip8 - turbo".to_string()
+                    } else if args.authentic_speed {
+                        "Rip8 - COSMAC VIP speed".to_string()
+                    } else {
+                        format!("Rip8 - {} Hz", frequency)
+                    };
+                    let _ = canvas.window_mut().set_title(&title);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => {
+                    if let Some(recorder) = recorder.take() {
+                        finish_recording(recorder);
+                    }
+                },
+                Event::Window { win_event: WindowEvent::Exposed, .. } => {
+                    redraw = true
+                },
+                // Mouse coordinates are in window units, which may not match
+                // the output's pixels on high DPI displays
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if args.virtual_keypad => {
+                    let (width, height) = canvas.window().size();
+                    clicked_key = virtual_keypad_layout(width, height, true).1.and_then(|area| keypad_key_at(area, x, y));
+                },
+                Event::MouseMotion { mousestate, x, y, .. } if args.virtual_keypad && mousestate.left() => {
+                    let (width, height) = canvas.window().size();
+                    clicked_key = virtual_keypad_layout(width, height, true).1.and_then(|area| keypad_key_at(area, x, y));
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    clicked_key = None;
+                },
                 _ => {}
             }
         }
 
-        // Process input
+        // Process input, keys can be held on the keyboard or the virtual
+        // keypad alike
         let keyboard_state = event_pump.keyboard_state();
-        for k in 0..SCANCODE_MAPPING.len() {
-            rip8.set_keydown(k, keyboard_state.is_scancode_pressed(SCANCODE_MAPPING[k]));
+        let mut keys: u16 = 0;
+        for (k, scancode) in key_mapping.iter().enumerate() {
+            if keyboard_state.is_scancode_pressed(*scancode) || clicked_key == Some(k) {
+                keys |= 1 << k;
+            }
+        }
+        if !frame_driven {
+            for k in 0..RIP8_KEY_COUNT {
+                rip8.set_keydown(k, keys & (1 << k) != 0);
+            }
         }
 
-        // Calculate delta since last step
-        cycles_due += cycles_per_frame;
-        let whole_cycles_due = cycles_due as u32;
-        for _ in 0..whole_cycles_due {
-            running &= rip8.step(1);
-            cycles_due -= 1.0;
+        // While paused no time goes by (so timers are frozen too), except
+        // for single steps. Otherwise timers tick once per frame, before
+        // running the frame's instructions. Turbo mode runs as many
+        // instructions as a frame allows whatever the frequency, but frames
+        // still last as long as usual so timers keep to wall-clock time
+        let mut outcome = StepOutcome::Continue;
+        if frame_driven {
+            let elapsed = last_tick_check.elapsed().as_secs_f64();
+            last_tick_check = Instant::now();
+            ticks_due = if paused { 0.0 } else { (ticks_due + elapsed * 60.0).min(MAX_TICKS_PER_FRAME) };
+            while !paused && ticks_due >= 1.0 && outcome == StepOutcome::Continue {
+                ticks_due -= 1.0;
+                if let Some((log, next)) = replay.as_mut() {
+                    match log.frames.get(*next) {
+                        Some(logged_keys) => {
+                            keys = *logged_keys;
+                            *next += 1;
+                        },
+                        None => {
+                            println!("Replay finished, paused");
+                            paused = true;
+                            break;
+                        }
+                    }
+                }
+                outcome = FrameDriver::drive_frame(&mut rip8, keys, cycles_per_tick as usize);
+                if let Some(log) = recording_log.as_mut() {
+                    log.frames.push(keys);
+                }
+            }
+        } else if paused && step_once {
+            let pc = rip8.get_pc();
+            let ir = rip8.peek_opcode();
+            match symbols.get(&pc) {
+                Some(name) => println!("{}: {}", name, disassemble_with_symbols(ir, &symbols)),
+                None => println!("0x{:03X}: {}", pc, disassemble_with_symbols(ir, &symbols)),
+            }
+            outcome = rip8.step();
+        } else if !paused {
+            cycles_due = if turbo { f32::MAX } else { cycles_due + cycles_per_frame };
+            rip8.tick_timers(frame_duration.as_secs_f64());
+        }
+        // Run instructions until the frame's cycles are spent, an instruction
+        // costing more than what's left borrows from the next frame
+        let mut executed = 0;
+        while !paused && !frame_driven && cycles_due >= 1.0 && outcome == StepOutcome::Continue {
+            if executed == args.max_cycles_per_frame {
+                // We can't keep up (huge frequency or no vsync), drop the excess
+                // so the event loop stays responsive
+                if !cycles_dropped && !turbo {
+                    println!("Can't keep up, dropping cycles in excess of {} instructions per frame!", args.max_cycles_per_frame);
+                    cycles_dropped = true;
+                }
+                cycles_due = if turbo { 0.0 } else { cycles_due.fract() };
+                break;
+            }
+            outcome = rip8.step();
+            executed += 1;
+            match rip8.get_last_step_cost() {
+                // Waiting for a key, or for the next tick under the display
+                // wait quirk, nothing else runs this frame
+                0 => cycles_due = if turbo { 0.0 } else { cycles_due.fract() },
+                cost => cycles_due -= cost as f32,
+            }
+        }
+        if let StepOutcome::BreakpointHit(addr) = outcome {
+            println!("Breakpoint hit at 0x{:03X}, press P to resume, N to step a single instruction or B to undo one", addr);
+            paused = true;
+            rip8.enable_delta_rewind(STEP_BACK_DEPTH);
+        } else if outcome != StepOutcome::Continue {
+            println!("Execution stopped: {}", outcome);
+            running = false;
+            let failed = matches!(outcome, StepOutcome::IllegalInstruction(_) | StepOutcome::StackUnderflow | StepOutcome::StackOverflow | StepOutcome::ProtectionFault(_));
+            if let (true, Some(path)) = (failed, &args.core_dump) {
+                match fs::write(path, rip8.dump_image()) {
+                    Ok(()) => println!("Memory saved to {} (pc was 0x{:03X})", path, rip8.get_pc()),
+                    Err(e) => println!("Could not save memory to {}: {}", path, e),
+                }
+            }
         }
 
         // Turn buzzer on/off & present screen
-        if rip8.is_tone_on() && !buzzer.is_on() {
+        if let Some(pattern) = rip8.get_audio_pattern() {
+            buzzer.set_pattern(pattern, rip8.get_audio_playback_rate());
+        }
+        let tone_on = rip8.is_tone_on() && !paused;
+        if tone_on && !buzzer.is_on() {
             buzzer.start();
-        } else if !rip8.is_tone_on() && buzzer.is_on() {
+        } else if !tone_on && buzzer.is_on() {
             buzzer.stop();
         }
 
-        for x in 0..RIP8_DISPLAY_WIDTH {
-            for y in 0..RIP8_DISPLAY_HEIGHT {
-                if rip8.get_display_spot(x, y) {
-                    canvas.set_draw_color(Color::GREEN);
-                } else {
-                    canvas.set_draw_color(Color::BLACK);
+        if args.show_speed && speed_check.elapsed() >= Duration::from_secs(1) {
+            let cycles = rip8.get_cycle_count();
+            let ips = cycles.saturating_sub(speed_check_cycles) as f64 / speed_check.elapsed().as_secs_f64();
+            let title = if args.authentic_speed {
+                format!("Rip8 - {:.0} IPS (COSMAC VIP speed)", ips)
+            } else {
+                format!("Rip8 - {:.0} IPS (target {})", ips, frequency)
+            };
+            let _ = canvas.window_mut().set_title(&title);
+            speed_check = Instant::now();
+            speed_check_cycles = cycles;
+        }
+
+        // Every loop iteration lasts a frame, whether it gets drawn or not
+        if let Some(rec) = recorder.as_mut() {
+            if let Err(e) = rec.add_frame(&rip8, frame_duration.as_secs_f64()) {
+                println!("Recording stopped: {}", e);
+                recorder = None;
+            }
+        }
+
+        let resized = rip8.take_resolution_changed();
+        if resized {
+            (texture_width, texture_height) = rip8.framebuffer_dimensions();
+            texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, texture_width as u32, texture_height as u32).unwrap();
+            intensity = vec![0.0; texture_width * texture_height];
+            planes = vec![0; texture_width * texture_height];
+        }
+
+        // Fades progress every frame, even while the display is untouched
+        if rip8.take_display_dirty() || resized || intensity.iter().any(|i| *i > 0.0 && *i < 1.0) {
+            redraw = true;
+            for y in 0..texture_height {
+                for x in 0..texture_width {
+                    let idx = y * texture_width + x;
+                    match rip8.get_display_pixel(x, y) {
+                        0 => intensity[idx] = (intensity[idx] - decay).max(0.0),
+                        pixel => {
+                            intensity[idx] = 1.0;
+                            planes[idx] = pixel;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Skip drawing if nothing changed, but wait for as long as presenting
+        // would have (because of vsync) to keep the pace
+        if !redraw {
+            thread::sleep(frame_duration);
+            continue;
+        }
+
+        canvas.set_draw_color(args.border);
+        canvas.clear();
+        let (output_width, output_height) = canvas.output_size().unwrap_or((args.width, args.height));
+        let (display_height, keypad_area) = virtual_keypad_layout(output_width, output_height, args.virtual_keypad);
+        let (offset_x, offset_y, spot_width, spot_height) = display_layout(output_width, display_height, texture_width, texture_height, args.integer_scale);
+        let _ = texture.with_lock(None, |pixels: &mut [u8], pitch: usize| {
+            for y in 0..texture_height {
+                for x in 0..texture_width {
+                    let brightness = intensity[y * texture_width + x];
+                    let on = palette[planes[y * texture_width + x] as usize];
+                    let blend = |on: u8, off: u8| (off as f32 + (on as f32 - off as f32) * brightness) as u8;
+                    let idx = y * pitch + x * 3;
+                    pixels[idx..idx + 3].copy_from_slice(&[blend(on.r, args.bg.r), blend(on.g, args.bg.g), blend(on.b, args.bg.b)]);
                 }
-                let spot_width: u32 = args.width / RIP8_DISPLAY_WIDTH as u32;
-                let spot_height: u32 = args.height / RIP8_DISPLAY_HEIGHT as u32;
-                let spot = Rect::new(
-                    x as i32 * spot_width as i32, y as i32 * spot_height as i32,
-                    spot_width, spot_height);
-                let _ = canvas.fill_rect(spot);
             }
+        });
+        let display_area = Rect::new(
+            offset_x, offset_y,
+            spot_width * texture_width as u32, spot_height * texture_height as u32);
+        let _ = canvas.copy(&texture, None, display_area);
+        if args.grid || args.scanlines {
+            draw_crt_overlay(&mut canvas, display_area, texture_width, texture_height, args.grid, args.scanlines);
+        }
+
+        if let Some(area) = keypad_area {
+            draw_keypad(&mut canvas, &rip8.keyboard_state(), area);
+        }
+        if show_keys {
+            draw_keypad_overlay(&mut canvas, &rip8.keyboard_state(), output_width, display_height);
         }
 
         canvas.present();
     }
+
+    // Quitting, or the program stopping, must not leave a truncated GIF behind
+    if let Some(recorder) = recorder {
+        finish_recording(recorder);
+    }
+    if args.profile {
+        print_opcode_histogram(&rip8);
+    }
+    if let (Some(path), Some(log)) = (&args.record_input, recording_log) {
+        match fs::write(path, log.serialize()) {
+            Ok(()) => println!("Input log saved to {} ({} frames)", path, log.frames.len()),
+            Err(e) => println!("Could not save input log to {}: {}", path, e),
+        }
+    }
 }
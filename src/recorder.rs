@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use gif::{Encoder, Frame, Repeat};
+
+use rip8::Rip8;
+
+// Records the display into an animated GIF. Consecutive identical frames are
+// merged into a longer one, so a mostly static screen doesn't bloat the file.
+// The clip keeps the size it started with, if the resolution changes spots
+// get resized to fit
+pub struct GifRecorder {
+    encoder: Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+    pending: Option<Vec<u8>>, // last frame seen, not written yet
+    pending_seconds: f64,
+    written_seconds: f64, // GIF delays are in 1/100s, keep track of the
+                          // rounding so the clip plays at real speed
+    recorded_seconds: f64,
+}
+
+impl GifRecorder {
+    // Colors are RGBA and indexed like `get_display_pixel`, but GIFs have no
+    // partial transparency so alpha is ignored
+    pub fn create(path: &str, rip8: &Rip8, scale: usize, palette: [[u8; 4]; 4]) -> Result<Self, String> {
+        let (width, height) = rip8.framebuffer_dimensions();
+        let width = (width * scale) as u16;
+        let height = (height * scale) as u16;
+        let palette: Vec<u8> = palette.iter().flat_map(|color| color[..3].to_vec()).collect();
+
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = Encoder::new(BufWriter::new(file), width, height, &palette).map_err(|e| e.to_string())?;
+        encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            pending: None,
+            pending_seconds: 0.0,
+            written_seconds: 0.0,
+            recorded_seconds: 0.0,
+        })
+    }
+
+    // Adds what the display looks like now, shown for `seconds`
+    pub fn add_frame(&mut self, rip8: &Rip8, seconds: f64) -> Result<(), String> {
+        let (columns, rows) = rip8.framebuffer_dimensions();
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(rip8.get_display_pixel(x * columns / width, y * rows / height));
+            }
+        }
+
+        if self.pending.as_ref() != Some(&pixels) {
+            self.flush()?;
+            self.pending = Some(pixels);
+        }
+        self.pending_seconds += seconds;
+        Ok(())
+    }
+
+    // Writes out the last frame and the GIF trailer
+    pub fn finish(mut self) -> Result<(), String> {
+        self.flush()?;
+        self.encoder.into_inner().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let pixels = match self.pending.take() {
+            Some(pixels) => pixels,
+            None => return Ok(()),
+        };
+        self.recorded_seconds += self.pending_seconds;
+        self.pending_seconds = 0.0;
+
+        let delay = ((self.recorded_seconds - self.written_seconds) * 100.0).round();
+        self.written_seconds += delay / 100.0;
+        let mut frame = Frame::from_indexed_pixels(self.width, self.height, pixels, None);
+        frame.delay = delay.min(u16::MAX as f64) as u16;
+        self.encoder.write_frame(&frame).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recorder::*;
+
+    #[test]
+    fn test_recording_is_finalized() {
+        let path = std::env::temp_dir().join(format!("rip8-test-{}.gif", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut rip8 = Rip8::from_rom(&[0x60, 0x05, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06], 480, || -> u8 { 0 });
+
+        let mut recorder = GifRecorder::create(path, &rip8, 2, [[0x00, 0x00, 0x00, 0xff], [0xff; 4], [0xff, 0x00, 0x00, 0xff], [0x00, 0x00, 0xff, 0xff]]).unwrap();
+        recorder.add_frame(&rip8, 1.0 / 60.0).unwrap();
+        rip8.run_cycles(3);
+        for _ in 0..10 {
+            recorder.add_frame(&rip8, 1.0 / 60.0).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(&bytes[..6], b"GIF89a");
+        assert_eq!(u16::from_le_bytes([bytes[6], bytes[7]]), 128);
+        assert_eq!(bytes.last(), Some(&0x3b)); // trailer
+    }
+}
@@ -3,1274 +3,5909 @@
 // - https://github.com/mattmikolay/chip-8/wiki/CHIP%E2%80%908-Instruction-Set
 // - http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
 pub const RIP8_MEMORY_SIZE: usize = 0x1000;
+pub const RIP8_XO_CHIP_MEMORY_SIZE: usize = 0x10000;
 pub const RIP8_ROM_START: u16 = 0x200;
-pub const RIP8_STACK_MAX_SIZE: usize = 0x40;
+pub const RIP8_STACK_MAX_SIZE: usize = 0x20; // return addresses
 pub const RIP8_DISPLAY_WIDTH: usize = 64;
 pub const RIP8_DISPLAY_HEIGHT: usize = 32;
+pub const RIP8_HIRES_DISPLAY_WIDTH: usize = 128; // S-CHIP's hires mode
+pub const RIP8_HIRES_DISPLAY_HEIGHT: usize = 64;
+pub const RIP8_DISPLAY_PLANE_COUNT: usize = 2;
+pub const RIP8_DISPLAY_SIZE: usize = RIP8_DISPLAY_WIDTH * RIP8_DISPLAY_HEIGHT / 8; // per plane
+pub const RIP8_HIRES_DISPLAY_SIZE: usize = RIP8_HIRES_DISPLAY_WIDTH * RIP8_HIRES_DISPLAY_HEIGHT / 8; // per plane
 pub const RIP8_KEY_COUNT: usize = 0x10;
-
-pub struct Rip8 {
-    pc: u16,
-    memory: Vec<u8>,
-    stack: Vec<u8>, // on the original COSMAC VIP interpreter the stack was
-                    // located on the main memory, but later implementations
-                    // differ and programs can't rely on the stack being on
-                    // any specifit memory location. Thus, we store it 
-                    // separately and keep the extra memory
-    v: [u8; 16],
-    i: u16,
-    display: Vec<bool>,
-    keyboard: [bool; RIP8_KEY_COUNT],
-    dt: u8,
-    st: u8,
-
-    freq: u32,
-    s_chip_mode: bool,
-    awaiting_input: bool,
-    awaiter_index: usize,
-    elapsed: f32,
-    get_random: fn() -> u8,
+pub const RIP8_RPL_FLAG_COUNT: usize = 8;
+pub const RIP8_AUDIO_PATTERN_SIZE: usize = 0x10;
+
+// Hex digit sprites, 5 bytes each, placed at the very start of memory
+pub const RIP8_FONT: [u8; 0x10 * 5] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0,
+    0x20, 0x60, 0x20, 0x20, 0x70,
+    0xf0, 0x10, 0xf0, 0x80, 0xf0,
+    0xf0, 0x10, 0xf0, 0x10, 0xf0,
+    0x90, 0x90, 0xf0, 0x10, 0x10,
+    0xf0, 0x80, 0xf0, 0x10, 0xf0,
+    0xf0, 0x80, 0xf0, 0x90, 0xf0,
+    0xf0, 0x10, 0x20, 0x40, 0x40,
+    0xf0, 0x90, 0xf0, 0x90, 0xf0,
+    0xf0, 0x90, 0xf0, 0x10, 0xf0,
+    0xf0, 0x90, 0xf0, 0x90, 0x90,
+    0xe0, 0x90, 0xe0, 0x90, 0xe0,
+    0xf0, 0x80, 0x80, 0x80, 0xf0,
+    0xe0, 0x90, 0x90, 0x90, 0xe0,
+    0xf0, 0x80, 0xf0, 0x80, 0xf0,
+    0xf0, 0x80, 0xf0, 0x80, 0x80];
+
+// Behaviors on which CHIP-8 implementations disagree, the defaults match
+// what this interpreter has always done
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    pub shift_uses_vy: bool,           // 8xy6/8xyE shift Vy into Vx, rather
+                                       // than shifting Vx in place
+    pub load_store_increments_i: bool, // Fx55/Fx65 leave I pointing past
+                                       // the last register loaded/stored
+    pub jump_with_vx: bool,            // Bnnn jumps to nnn + Vx (x being the
+                                       // high nibble of nnn) instead of V0
+    pub logic_resets_vf: bool,         // 8xy1/8xy2/8xy3 set VF to 0
+    pub clip_sprites: bool,            // sprites are cut at the edges of the
+                                       // screen rather than wrapping around
+    pub display_wait: bool,            // Dxyn halts execution until the next
+                                       // 60hz tick, so at most one sprite is
+                                       // drawn per frame
+    pub count_collided_rows: bool,     // In hires, Dxyn sets VF to the
+                                       // number of sprite rows that collided
+                                       // plus the ones clipped off the
+                                       // bottom, like S-CHIP does. Lores
+                                       // still sets it to 0 or 1
+    pub zero_registers: bool,          // V0-VF and I start out as 0 instead
+                                       // of 0xff, like most interpreters
+                                       // (ROMs that skip initializing them
+                                       // tend to assume 0). Off by default
+                                       // since 0xff is what this interpreter
+                                       // has always done. Only looked at
+                                       // when the machine is built or reset
+    pub trap_sys: bool,                // 0nnn (calling a machine code routine,
+                                       // which can't be emulated) halts as an
+                                       // illegal instruction instead of being
+                                       // skipped
+    pub i_overflow_sets_vf: bool,      // Fx1E sets VF to 1 when I goes past
+                                       // 0xFFF and to 0 otherwise, as the
+                                       // Amiga interpreter did ("Spacefight
+                                       // 2091!" relies on it)
+    pub large_sprites: bool,           // Dxy0 draws an 8x16 sprite in lores
+                                       // like S-CHIP, rather than nothing
+                                       // (hires always draws 16x16)
 }
 
-impl Rip8 {
-    pub fn from_image_at_start(image: &Vec<u8>, freq: u32, start_address: u16, get_random: fn() -> u8) -> Self {
-        assert!(image.len() == RIP8_MEMORY_SIZE);
-
+impl Default for Quirks {
+    fn default() -> Self {
         Self {
-            pc: start_address,
-            memory: image.clone(),
-            stack: Vec::with_capacity(RIP8_STACK_MAX_SIZE),
-            v: [0xff; 16],
-            i: 0xff,
-            display: vec![false; RIP8_DISPLAY_WIDTH * RIP8_DISPLAY_HEIGHT],
-            keyboard: [false; RIP8_KEY_COUNT],
-            dt: 0x00,
-            st: 0x00,
-
-            freq,
-            s_chip_mode: false,
-            awaiting_input: false,
-            awaiter_index: 0,
-            elapsed: 0.0,
-            get_random,
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            count_collided_rows: false,
+            zero_registers: false,
+            trap_sys: false,
+            i_overflow_sets_vf: false,
+            large_sprites: false,
         }
     }
+}
 
-    pub fn from_image(image: &Vec<u8>, freq: u32, get_random: fn() -> u8) -> Self {
-        Self::from_image_at_start(image, freq, RIP8_ROM_START, get_random)
-    }
-
-    pub fn from_rom_at_address(rom: &Vec<u8>, freq: u32, loading_address: u16, get_random: fn() -> u8) -> Self {
-        assert!(loading_address >= RIP8_ROM_START);
-        assert!(rom.len() <= RIP8_MEMORY_SIZE - loading_address as usize);
-
-        let mut memory: Vec<u8> = Vec::with_capacity(RIP8_MEMORY_SIZE);
-
-        let font_data: [u8; 0x10 * 5] = [
-            0xf0, 0x90, 0x90, 0x90, 0xf0,
-            0x20, 0x60, 0x20, 0x20, 0x70,
-            0xf0, 0x10, 0xf0, 0x80, 0xf0,
-            0xf0, 0x10, 0xf0, 0x10, 0xf0,
-            0x90, 0x90, 0xf0, 0x10, 0x10,
-            0xf0, 0x80, 0xf0, 0x10, 0xf0,
-            0xf0, 0x80, 0xf0, 0x90, 0xf0,
-            0xf0, 0x10, 0x20, 0x40, 0x40,
-            0xf0, 0x90, 0xf0, 0x90, 0xf0,
-            0xf0, 0x90, 0xf0, 0x10, 0xf0,
-            0xf0, 0x90, 0xf0, 0x90, 0x90,
-            0xe0, 0x90, 0xe0, 0x90, 0xe0,
-            0xf0, 0x80, 0x80, 0x80, 0xf0,
-            0xe0, 0x90, 0x90, 0x90, 0xe0,
-            0xf0, 0x80, 0xf0, 0x80, 0xf0,
-            0xf0, 0x80, 0xf0, 0x80, 0x80];
-
-        // Fill reserved memory region
-        for i in 0..loading_address as usize {
-            if i < font_data.len() {
-                memory.push(font_data[i]);
-            } else {
-                memory.push(0xff);
-            }
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+            count_collided_rows: false,
+            zero_registers: false,
+            trap_sys: false,
+            i_overflow_sets_vf: false,
+            large_sprites: false,
         }
+    }
 
-        // Copy rom code, pad as needed
-        for i in 0..rom.len() {
-            memory.push(rom[i]);
+    pub fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            jump_with_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            count_collided_rows: false,
+            zero_registers: false,
+            trap_sys: false,
+            i_overflow_sets_vf: false,
+            large_sprites: false,
         }
+    }
 
-        let needed = RIP8_MEMORY_SIZE - memory.len();
-        for _ in 0..needed {
-            memory.push(0xff);
+    pub fn schip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_with_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            count_collided_rows: false,
+            zero_registers: false,
+            trap_sys: false,
+            i_overflow_sets_vf: false,
+            large_sprites: true,
         }
-
-        Self::from_image_at_start(&memory, freq, loading_address, get_random)
     }
+}
 
-    pub fn from_rom(rom: &Vec<u8>, freq: u32, get_random: fn() -> u8) -> Self {
-        Self::from_rom_at_address(rom, freq, RIP8_ROM_START, get_random)
-    }
+// How many machine cycles each kind of instruction takes, as reported by
+// `get_last_step_cost`. The defaults make every instruction cost one, so
+// that cycles and instructions are the same thing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timing {
+    pub clear: u32,               // 00E0
+    pub flow: u32,                // 00EE, 1nnn, 2nnn, Bnnn
+    pub skip: u32,                // 3xkk, 4xkk, 5xy0, 9xy0, Ex9E, ExA1
+    pub register: u32,            // 6xkk to Cxkk, and Fx07 to Fx29
+    pub draw: u32,                // Dxyn, plus `draw_per_row` for each of
+    pub draw_per_row: u32,        // the sprite's n rows
+    pub memory: u32,              // Fx33, Fx55, Fx65, plus
+    pub memory_per_register: u32, // `memory_per_register` per byte moved
+    pub other: u32,               // S-CHIP and XO-CHIP instructions
+}
 
-    pub fn set_s_chip_mode(&mut self, s_chip_mode: bool) {
-        self.s_chip_mode = s_chip_mode;
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            clear: 1,
+            flow: 1,
+            skip: 1,
+            register: 1,
+            draw: 1,
+            draw_per_row: 0,
+            memory: 1,
+            memory_per_register: 0,
+            other: 1,
+        }
     }
+}
 
-    pub fn set_keydown(&mut self, k: usize, v: bool) {
-        if k < 0x10 {
-            // Handling keydown events is a bit involved because of the fx0a
-            // instruction, for more information see:
-            // https://retrocomputing.stackexchange.com/a/361
-            if self.keyboard[k] && !v && self.awaiting_input {
-                self.awaiting_input = false;
-                self.v[self.awaiter_index] = k as u8;
-            }
-            self.keyboard[k] = v;
+impl Timing {
+    // Rough costs on the COSMAC VIP, in CDP1802 machine cycles (8 clock
+    // cycles each) and including the interpreter's own fetch and decode
+    // overhead. Real timings also depend on operands (such as the sprite's
+    // horizontal position), these are averages
+    pub fn cosmac_vip() -> Self {
+        Self {
+            clear: 1600,
+            flow: 110,
+            skip: 100,
+            register: 100,
+            draw: 300,
+            draw_per_row: 250,
+            memory: 150,
+            memory_per_register: 80,
+            other: 100,
         }
     }
 
-    pub fn get_display_spot(&self, mut x: usize, mut y: usize) -> bool {
-        x = x % RIP8_DISPLAY_WIDTH;
-        y = y % RIP8_DISPLAY_HEIGHT;
-        self.display[y * RIP8_DISPLAY_WIDTH + x]
+    pub fn cost(&self, instruction: Instruction) -> u32 {
+        match instruction {
+            Instruction::Cls => self.clear,
+            Instruction::Ret | Instruction::Jp(_) | Instruction::Call(_) | Instruction::JpV0(_) => self.flow,
+            Instruction::SeVxKk(..) | Instruction::SneVxKk(..) | Instruction::SeVxVy(..) |
+            Instruction::SneVxVy(..) | Instruction::Skp(_) | Instruction::Sknp(_) => self.skip,
+            Instruction::LdVxKk(..) | Instruction::AddVxKk(..) | Instruction::LdVxVy(..) |
+            Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) |
+            Instruction::AddVxVy(..) | Instruction::Sub(..) | Instruction::Shr(..) |
+            Instruction::Subn(..) | Instruction::Shl(..) | Instruction::LdI(_) |
+            Instruction::Rnd(..) | Instruction::LdVxDt(_) | Instruction::LdVxK(_) |
+            Instruction::LdDtVx(_) | Instruction::LdStVx(_) | Instruction::AddIVx(_) |
+            Instruction::LdFVx(_) => self.register,
+            Instruction::Drw(_, _, n) => self.draw + self.draw_per_row * n as u32,
+            Instruction::LdBVx(_) => self.memory + self.memory_per_register * 3,
+            Instruction::LdIVx(x) | Instruction::LdVxI(x) => self.memory + self.memory_per_register * (x as u32 + 1),
+            Instruction::Exit | Instruction::Low | Instruction::High |
+            Instruction::ScrollUp(_) | Instruction::LdILong |
+            Instruction::Plane(_) | Instruction::Audio | Instruction::Pitch(_) |
+            Instruction::LdRVx(_) | Instruction::LdVxR(_) | Instruction::SaveVxVy(..) |
+            Instruction::LoadVxVy(..) | Instruction::Sys(_) | Instruction::Illegal(_) => self.other,
+        }
     }
+}
 
-    pub fn is_tone_on(&self) -> bool {
-        self.st != 0
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Continue,
+    IllegalInstruction(u16),
+    StackUnderflow,
+    StackOverflow,
+    Exited, // the program ran S-CHIP's exit instruction
+    BreakpointHit(u16),
+    WatchpointHit { addr: u16, old: u8, new: u8 }, // the write already
+                                                   // happened
+    RegisterChanged { reg: usize, old: u8, new: u8 },
+    BudgetExceeded, // see `set_instruction_budget`
+    ProtectionFault(u16), // see `set_memory_protection`, the write was
+                          // dropped
+    VblankWait, // see `set_report_vblank_waits`
+    NoDraw, // `step_until_draw` ran out of cycles
+}
 
-    fn set_spot(&mut self, mut x: usize, mut y: usize, val: bool) -> bool {
-        let mut unset = false;
-        x = x % RIP8_DISPLAY_WIDTH;
-        y = y % RIP8_DISPLAY_HEIGHT;
-        if self.display[y * RIP8_DISPLAY_WIDTH + x] && val {
-            unset = true;
+impl fmt::Display for StepOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepOutcome::Continue => write!(f, "running"),
+            StepOutcome::IllegalInstruction(ir) => write!(f, "illegal instruction 0x{:04X}", ir),
+            StepOutcome::StackUnderflow => write!(f, "stack underflow"),
+            StepOutcome::StackOverflow => write!(f, "stack overflow"),
+            StepOutcome::Exited => write!(f, "program exited"),
+            StepOutcome::BreakpointHit(addr) => write!(f, "breakpoint hit at 0x{:03X}", addr),
+            StepOutcome::WatchpointHit { addr, old, new } => write!(f, "watchpoint hit at 0x{:03X} (0x{:02X} -> 0x{:02X})", addr, old, new),
+            StepOutcome::RegisterChanged { reg, old, new } => write!(f, "V{:X} changed (0x{:02X} -> 0x{:02X})", reg, old, new),
+            StepOutcome::BudgetExceeded => write!(f, "instruction budget exceeded"),
+            StepOutcome::ProtectionFault(addr) => write!(f, "write to reserved memory at 0x{:03X}", addr),
+            StepOutcome::VblankWait => write!(f, "waiting for vertical blank"),
+            StepOutcome::NoDraw => write!(f, "nothing was drawn"),
         }
-        self.display[y * RIP8_DISPLAY_WIDTH + x] ^= val;
-        unset
     }
+}
 
-    pub fn step(&mut self, delta_cycles: u32) -> bool {
-        self.elapsed += delta_cycles as f32;
+// How much memory the machine has. CHIP-8 programs get 4KB, XO-CHIP ones
+// can address a full 64KB (through F000 long loads)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryModel {
+    #[default]
+    Chip8,
+    XoChip,
+}
 
-        // Timers count down at 60hz
-        let tick_cycles = self.freq as f32 / 60.0;
-        while self.elapsed >= tick_cycles {
-            self.dt = self.dt.saturating_sub(1);
-            self.st = self.st.saturating_sub(1);
-            self.elapsed -= tick_cycles;
-        }
+// How the 16-bit words of an image are laid out. CHIP-8 opcodes are big
+// endian, but some tools dump memory with every word byte-swapped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    #[default]
+    BigEndian,
+    LittleEndian,
+}
 
-        // fetch
-        if self.awaiting_input {
-            return true
-        }
-
-        let ir_hb = self.memory[self.pc as usize];
-        self.pc = self.pc.wrapping_add(1);
-        let ir_lb = self.memory[self.pc as usize];
-        self.pc = self.pc.wrapping_add(1);
-        let ir: u16 = u16::from_be_bytes([ir_hb, ir_lb]);
-
-        // decode { exec }
-        let x: usize = ((ir & 0x0f00) >> 8) as usize;
-        let y: usize = ((ir & 0x00f0) >> 4) as usize;
-        let k: u8 = (ir & 0x00ff) as u8;
-        let i: u16 = ir & 0x0fff;
-        let n: u8 = (ir & 0x000f) as u8; // this should really be a nibble,
-                                         // but there is no u4 in rust
-        if ir & 0xffff == 0x00e0 {
-            for i in 0..self.display.len() {
-                self.display[i] = false;
-            }
-        } else if ir & 0xffff == 0x00ee {
-            if self.stack.len() < 2 {
-                // stack underflow
-                return false
-            }
-            self.pc = (self.stack.pop().unwrap() as u16) << 8;
-            self.pc |= self.stack.pop().unwrap() as u16;
-        } else if ir & 0xf000 == 0x1000 {
-            self.pc = i;
-        } else if ir & 0xf000 == 0x2000 {
-            if self.stack.len() > RIP8_STACK_MAX_SIZE - 2 {
-                // stack overflow
-                return false
-            }
-            self.stack.push(((self.pc >> 0) & 0xff) as u8);
-            self.stack.push(((self.pc >> 8) & 0xff) as u8);
-            self.pc = i;
-        } else if ir & 0xf000 == 0x3000 {
-            if self.v[x] == k {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf000 == 0x4000 {
-            if self.v[x] != k {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf00f == 0x5000 {
-            if self.v[x] == self.v[y] {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf000 == 0x6000 {
-            self.v[x] = k;
-        } else if ir & 0xf000 == 0x7000 {
-            self.v[x] = self.v[x].wrapping_add(k);
-        } else if ir & 0xf00f == 0x8000 {
-            self.v[x] = self.v[y];
-        } else if ir & 0xf00f == 0x8001 {
-            self.v[x] |= self.v[y];
-        } else if ir & 0xf00f == 0x8002 {
-            self.v[x] &= self.v[y];
-        } else if ir & 0xf00f == 0x8003 {
-            self.v[x] ^= self.v[y];
-        } else if ir & 0xf00f == 0x8004 {
-            let (v, o) = self.v[x].overflowing_add(self.v[y]);
-            self.v[x] = v;
-            self.v[0xf] = if o { 1 } else { 0 };
-        } else if ir & 0xf00f == 0x8005 {
-            let (v, o) = self.v[x].overflowing_sub(self.v[y]);
-            self.v[x] = v;
-            self.v[0xf] = if o { 0 } else { 1 };
-        } else if ir & 0xf00f == 0x8006 {
-            let o = if self.s_chip_mode { x } else { y };
-            self.v[0xf] = self.v[o] & 0x1;
-            self.v[x] = self.v[o].overflowing_shr(1).0;
-        } else if ir & 0xf00f == 0x8007 {
-            let (v, o) = self.v[y].overflowing_sub(self.v[x]);
-            self.v[x] = v;
-            self.v[0xf] = if o { 0 } else { 1 };
-        } else if ir & 0xf00f == 0x800e {
-            let o = if self.s_chip_mode { x } else { y };
-            self.v[0xf] = (self.v[o] & 0x80) >> 7;
-            self.v[x] = self.v[o].overflowing_shl(1).0;
-        } else if ir & 0xf00f == 0x9000 {
-            if self.v[x] != self.v[y] {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf000 == 0xa000 {
-            self.i = i;
-        } else if ir & 0xf000 == 0xb000 {
-            self.pc = i.wrapping_add(self.v[0] as u16);
-        } else if ir & 0xf000 == 0xc000 {
-            self.v[x] = (self.get_random)() & k;
-        } else if ir & 0xf000 == 0xd000 {
-            let mut unset_bits = false;
-            for idx in 0..n {
-                for s in 0..8 {
-                    let spot_byte = self.memory[self.i as usize + idx as usize];
-                    let spot = ((spot_byte >> (7-s)) & 0x01) != 0x00;
-                    unset_bits |= self.set_spot(self.v[x] as usize + s,
-                                    (self.v[y] + idx) as usize,
-                                    spot);
-                }
-            }
-            self.v[0xf] = if unset_bits { 1 } else { 0 }
-        } else if ir & 0xf0ff == 0xe09e {
-            if self.keyboard[self.v[x] as usize] {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf0ff == 0xe0a1 {
-            if !self.keyboard[self.v[x] as usize] {
-                self.pc = self.pc.wrapping_add(2);
-            }
-        } else if ir & 0xf0ff == 0xf007 {
-            self.v[x] = self.dt;
-        } else if ir & 0xf0ff == 0xf00a {
-            self.awaiting_input = true;
-            self.awaiter_index = x;
-        } else if ir & 0xf0ff == 0xf015 {
-            self.dt = self.v[x];
-        } else if ir & 0xf0ff == 0xf018 {
-            self.st = self.v[x];
-        } else if ir & 0xf0ff == 0xf01e {
-            self.i = self.i.wrapping_add(self.v[x] as u16);
-        } else if ir & 0xf0ff == 0xf029 {
-            self.i = (self.v[x] & 0xf) as u16 * 5;
-        } else if ir & 0xf0ff == 0xf033 {
-            self.memory[self.i as usize + 0] = (self.v[x] / 100) % 10;
-            self.memory[self.i as usize + 1] = (self.v[x] / 10) % 10;
-            self.memory[self.i as usize + 2] = (self.v[x] / 1) % 10;
-        } else if ir & 0xf0ff == 0xf055 {
-            for r in 0..(x+1) {
-                self.memory[self.i as usize + r] = self.v[r];
-            }
-            if !self.s_chip_mode {
-                self.i = self.i.wrapping_add(x as u16 + 1);
-            }
-        } else if ir & 0xf0ff == 0xf065 {
-            for r in 0..(x+1) {
-                self.v[r] = self.memory[self.i as usize + r];
-            }
-            if !self.s_chip_mode {
-                self.i = self.i.wrapping_add(x as u16 + 1);
-            }
-        } else {
-            // could not parse instruction, halt and catch fire
-            return false
+impl MemoryModel {
+    pub fn memory_size(&self) -> usize {
+        match self {
+            MemoryModel::Chip8 => RIP8_MEMORY_SIZE,
+            MemoryModel::XoChip => RIP8_XO_CHIP_MEMORY_SIZE,
         }
-        true
+    }
+
+    fn from_memory_size(size: usize) -> Option<Self> {
+        [MemoryModel::Chip8, MemoryModel::XoChip].into_iter().find(|model| model.memory_size() == size)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::rip8::*;
-    const ALWAYS_42: fn() -> u8 = || -> u8 { 0x42 };
-    const ALWAYS_ZERO: fn() -> u8 = || -> u8 { 0x00 };
-    const DEFAULT_FREQUENCY: u32 = 480;
+// Where Cxkk gets its random numbers from. Anything callable works, plain
+// functions as well as closures keeping their own state (such as a seeded
+// generator, for reproducible runs)
+pub struct RandomSource(Box<dyn FnMut() -> u8>);
 
-   fn rip8_with_rom(rom: &Vec<u8>) -> Rip8 {
-        Rip8::from_rom(rom, DEFAULT_FREQUENCY, ALWAYS_ZERO)
+impl<F: FnMut() -> u8 + 'static> From<F> for RandomSource {
+    fn from(get_random: F) -> Self {
+        RandomSource(Box::new(get_random))
     }
+}
 
-    fn run(rip8: &mut Rip8) {
-        while rip8.step(1) { }
+// Reasons a ROM, image, save state, input log or symbol file can be refused
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rip8Error {
+    ImageWrongSize(usize), // size of the offending image
+    RomTooLarge(usize), // size of the offending rom
+    LoadAddressTooLow(u16),
+    StartAddressOutOfRange(u16),
+    SegmentOutOfRange(u16, usize), // address and length of the segment
+    SaveStateBadMagic,
+    SaveStateVersion(u8), // version found in the save state
+    SaveStateTruncated,
+    SaveStateCorrupt,
+    InputLogBadMagic,
+    InputLogVersion(u8), // version found in the input log
+    InputLogTruncated,
+    SymbolsMalformed(usize), // line number, starting from 1
+}
+
+impl fmt::Display for Rip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rip8Error::ImageWrongSize(size) => write!(f, "image is {} bytes long, expected {} or {}", size, RIP8_MEMORY_SIZE, RIP8_XO_CHIP_MEMORY_SIZE),
+            Rip8Error::RomTooLarge(size) => write!(f, "rom is {} bytes long, it doesn't fit in memory", size),
+            Rip8Error::LoadAddressTooLow(addr) => write!(f, "loading address 0x{:03X} overlaps the reserved region (below 0x{:03X})", addr, RIP8_ROM_START),
+            Rip8Error::StartAddressOutOfRange(addr) => write!(f, "start address 0x{:03X} is past the end of memory", addr),
+            Rip8Error::SegmentOutOfRange(addr, len) => write!(f, "{} bytes at 0x{:03X} go past the end of memory", len, addr),
+            Rip8Error::SaveStateBadMagic => write!(f, "not a save state"),
+            Rip8Error::SaveStateVersion(version) => write!(f, "unsupported save state version {}", version),
+            Rip8Error::SaveStateTruncated => write!(f, "save state is truncated"),
+            Rip8Error::SaveStateCorrupt => write!(f, "save state holds an impossible machine state"),
+            Rip8Error::InputLogBadMagic => write!(f, "not an input log"),
+            Rip8Error::InputLogVersion(version) => write!(f, "unsupported input log version {}", version),
+            Rip8Error::InputLogTruncated => write!(f, "input log is truncated"),
+            Rip8Error::SymbolsMalformed(line) => write!(f, "line {} of the symbol file is not an address followed by a name", line),
+        }
     }
+}
 
-    fn run_rom_with_random(rom: &Vec<u8>, random: fn() -> u8) -> Rip8 {
-        let mut rip8 = Rip8::from_rom(rom, 480, random);
-        run(&mut rip8);
-        rip8
+impl core::error::Error for Rip8Error {}
+
+// Save state format, all multi-byte values are big endian:
+//   magic "RIP8", version (1 byte)
+//   pc (2), i (2), v (16), dt (1), st (1)
+//   stack length (1, in return addresses), stack (2 per return address)
+//   memory model (1, 0 for CHIP-8 and 1 for XO-CHIP)
+//   memory (memory size), initial memory (memory size)
+//   display (RIP8_HIRES_DISPLAY_SIZE * RIP8_DISPLAY_PLANE_COUNT), hires (1)
+//   keyboard (RIP8_KEY_COUNT, one byte per key)
+//   awaiting input (1), awaiter index (1), awaiting vblank (1)
+//   released keys (2, keys released during Fx0A's wait, one bit per key)
+//   rpl flags (RIP8_RPL_FLAG_COUNT)
+//   has audio pattern (1), audio pattern (RIP8_AUDIO_PATTERN_SIZE), audio pitch (1)
+//   selected planes (1), start address (2), frequency (4)
+//   elapsed (8, f64 bits, fraction of a 60hz tick since the last one)
+//   quirks (2, one bit per flag in declaration order)
+const RIP8_SAVE_STATE_MAGIC: &[u8; 4] = b"RIP8";
+const RIP8_SAVE_STATE_VERSION: u8 = 7;
+
+struct SaveStateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SaveStateReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Rip8Error> {
+        if self.bytes.len() - self.pos < count {
+            return Err(Rip8Error::SaveStateTruncated);
+        }
+        let taken = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(taken)
     }
 
-    fn run_rom(rom: &Vec<u8>) -> Rip8 {
-        run_rom_with_random(rom, ALWAYS_ZERO)
+    fn byte(&mut self) -> Result<u8, Rip8Error> {
+        Ok(self.take(1)?[0])
     }
 
-    fn append_trailing_data_to_rom(code: &mut Vec<u8>, mut trailing_data: Vec<u8>) -> u16 {
-        let sprite_length = trailing_data.len();
-        let sprite_address = RIP8_ROM_START + (code.len() & 0xffff) as u16 + 2;
+    fn flag(&mut self) -> Result<bool, Rip8Error> {
+        Ok(self.byte()? != 0)
+    }
 
-        code.append(&mut trailing_data);
+    fn word(&mut self) -> Result<u16, Rip8Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
 
-        code.insert(0, 0xa0 | (sprite_address >> 8) as u8);
-        code.insert(1, (sprite_address & 0xff) as u8);
+    fn dword(&mut self) -> Result<u32, Rip8Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
 
-        RIP8_ROM_START + (code.len() - sprite_length) as u16
+    fn qword(&mut self) -> Result<u64, Rip8Error> {
+        let mut bytes = [0x00; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
     }
+}
 
-    #[test]
-    fn test_jp_zero() {
-        let rom = vec![0x10, 0x00];
+// State of the machine right after an instruction is fetched and before it
+// is executed, `pc` being the address the instruction was fetched from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub uninitialized_reads: u16, // registers the instruction reads that
+                                  // were never written, one bit per register
+                                  // (see `set_warn_uninitialized_reads`)
+}
 
-        let rip8 = run_rom(&rom);
+// Everything needed to undo a single step, memory and display are tracked
+// byte by byte (in the order they were written) so that a long rewind window
+// doesn't need a full copy of them per instruction
+struct RewindDelta {
+    pc: u16,
+    stack_len: usize,
+    stack_top: Option<u16>,
+    v: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    rpl: [u8; RIP8_RPL_FLAG_COUNT],
+    audio_pattern: Option<[u8; RIP8_AUDIO_PATTERN_SIZE]>,
+    audio_pitch: u8,
+    selected_planes: u8,
+    hires: bool,
+    awaiting_input: bool,
+    awaiter_index: usize,
+    released_keys: u16,
+    awaiting_vblank: bool,
+    elapsed: f64,
+    memory: Vec<(usize, u8)>,
+    display: Vec<(usize, u8)>,
+}
 
-        assert_eq!(rip8.pc, 0x0002)
-    }
+// With the `serde` feature the machine can be (de)serialized as a whole.
+// The random number source, hooks and rewind history are left out, use
+// `with_random` to plug a random number source back after deserializing
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rip8 {
+    pc: u16,
+    memory: Vec<u8>,
+    stack: Vec<u16>, // on the original COSMAC VIP interpreter the stack was
+                     // located on the main memory, but later implementations
+                     // differ and programs can't rely on the stack being on
+                     // any specifit memory location. Thus, we store it 
+                     // separately (as plain return addresses) and keep the
+                     // extra memory
+    v: [u8; 16],
+    i: u16,
+    display: Vec<u8>, // bit-packed, one plane after the other, each plane
+                      // is row-major with the leftmost spot in the MSB.
+                      // Planes are RIP8_HIRES_DISPLAY_SIZE bytes apart,
+                      // lores only uses the start of each
+    keyboard: [bool; RIP8_KEY_COUNT],
+    dt: u8,
+    st: u8,
+    rpl: [u8; RIP8_RPL_FLAG_COUNT], // S-CHIP "RPL user flags", on the HP48
+                                    // these lived outside the interpreter's
+                                    // memory and survived between runs
+    audio_pattern: Option<[u8; RIP8_AUDIO_PATTERN_SIZE]>, // only set once
+                                                          // an XO-CHIP ROM
+                                                          // loads one
+    audio_pitch: u8,
+
+    start_address: u16,
+    initial_memory: Vec<u8>, // memory as it was right after loading, so that
+                             // `reset` doesn't need the ROM again
+    freq: u32, // nominal instructions per second, only used to pace
+               // `run_until_halt`, frontends decide how fast to step
+    memory_model: MemoryModel,
+    quirks: Quirks,
+    timing: Timing,
+    selected_planes: u8,
+    awaiting_input: bool,
+    awaiter_index: usize,
+    released_keys: u16, // keys released during Fx0A's wait, one bit per key
+    warn_uninitialized_reads: bool,
+    written: u16, // registers assigned since loading (or resetting), one bit
+                  // per register, only tracked while warning about reads
+    awaiting_vblank: bool,
+    elapsed: f64, // fraction of a 60hz tick gone by since the last one
+    display_dirty: bool,
+    hires: bool,
+    resolution_changed: bool,
+    #[cfg_attr(feature = "serde", serde(skip, default = "no_random"))]
+    get_random: RandomSource,
+    rewind_depth: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rewind_deltas: VecDeque<RewindDelta>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace_hook: Option<Box<dyn FnMut(TraceEvent)>>,
+    breakpoints: BTreeSet<u16>,
+    resuming_from_breakpoint: bool,
+    watchpoints: BTreeSet<u16>,
+    watchpoint_hit: Option<(u16, u8, u8)>, // first watched write of the
+                                           // current step: address, old and
+                                           // new value
+    watched_registers: u16, // one bit per V register
+    memory_protection: bool,
+    or_draw: bool, // see `set_or_draw`
+    report_vblank_waits: bool,
+    protection_fault: Option<u16>, // first write the current step had
+                                   // dropped
+    instruction_budget: Option<u32>,
+    budget_used: u32, // instructions fetched since the budget was refilled
+    profiling: bool,
+    #[cfg_attr(feature = "serde", serde(skip, default = "no_opcode_counts"))]
+    opcode_counts: [u64; PROFILE_SLOT_COUNT], // see `profile_slot`
+    cycle_count: u64, // instructions fetched since loading (or resetting),
+                      // rewinding doesn't take it back
+    last_step_cost: u32, // machine cycles the last step took, see `Timing`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    decoded: Vec<Option<(u16, Instruction)>>, // every instruction
+                                              // fetched so far (and its
+                                              // opcode), indexed by address
+                                              // and thrown away whenever
+                                              // memory changes
+}
 
-    #[test]
-    fn test_jp_chained() {
-        let rom = vec![0x12, 0x02, 0x10, 0x00];
+// FNV-1a, unlike std's hashers it's guaranteed to give the same result
+// everywhere
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
 
-        let rip8 = run_rom(&rom);
+// Stand-in random number source for deserialized machines and builders
+// that weren't given one
+fn no_random() -> RandomSource {
+    RandomSource::from(|| -> u8 { 0x00 })
+}
 
-        assert_eq!(rip8.pc, 0x0002)
-    }
+// Deserialized machines start a fresh profile
+#[cfg(feature = "serde")]
+fn no_opcode_counts() -> [u64; PROFILE_SLOT_COUNT] {
+    [0; PROFILE_SLOT_COUNT]
+}
 
-    #[test]
-    fn test_call_zero() {
-        let rom = vec![0x20, 0x00];
+// What to put in memory when building a machine
+enum Program {
+    Rom(Vec<u8>),   // code only, loaded over a fresh memory
+    Image(Vec<u8>), // the whole memory, which also fixes the memory model
+}
 
-        let rip8 = run_rom(&rom);
+// Chainable alternative to the `from_*` constructors, anything left unset
+// keeps its default: an empty ROM at RIP8_ROM_START, 540hz, plain CHIP-8
+// memory with the default quirks and timing, and Cxkk always yielding 0
+pub struct Rip8Builder {
+    program: Program,
+    load_address: u16, // where the ROM goes, or where an image starts running
+    start_address: Option<u16>, // where execution starts, if not there
+    freq: u32,
+    memory_model: MemoryModel, // ignored for images
+    byte_order: ByteOrder, // only applies to images
+    quirks: Quirks,
+    timing: Timing,
+    get_random: RandomSource,
+}
 
-        assert_eq!(rip8.pc, 0x0002);
-        assert_eq!(
-            u16::from_be_bytes([
-                rip8.stack[rip8.stack.len() - 2],
-                rip8.stack[rip8.stack.len() - 1],]),
-            0x0202);
+impl Default for Rip8Builder {
+    fn default() -> Self {
+        Self {
+            program: Program::Rom(Vec::new()),
+            load_address: RIP8_ROM_START,
+            start_address: None,
+            freq: 540,
+            memory_model: MemoryModel::Chip8,
+            byte_order: ByteOrder::BigEndian,
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            get_random: no_random(),
+        }
     }
+}
 
-    #[test]
-    fn test_ld_const() {
-        let rom = vec![0x60, 0x12, 0x6c, 0x54];
-
-        let rip8 = run_rom(&rom);
+impl Rip8Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x12);
-        assert_eq!(rip8.v[0xc], 0x54);
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.program = Program::Rom(rom.to_vec());
+        self
     }
 
-    #[test]
-    fn test_se_const_taken() {
-        let rom = vec![0x60, 0x12, 0x30, 0x12];
+    pub fn image(mut self, image: &[u8]) -> Self {
+        self.program = Program::Image(image.to_vec());
+        self
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn load_address(mut self, load_address: u16) -> Self {
+        self.load_address = load_address;
+        self
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x12);
+    // Starts execution somewhere other than the load address, so that the
+    // ROM can be loaded as data and run by code written elsewhere
+    pub fn start_address(mut self, start_address: u16) -> Self {
+        self.start_address = Some(start_address);
+        self
     }
 
-    #[test]
-    fn test_se_const_not_taken() {
-        let rom = vec![0x60, 0x12, 0x30, 0x13];
+    pub fn freq(mut self, freq: u32) -> Self {
+        self.freq = freq;
+        self
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn memory_model(mut self, memory_model: MemoryModel) -> Self {
+        self.memory_model = memory_model;
+        self
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x12);
+    // Images given as little endian get every pair of bytes swapped when
+    // loaded, ROMs are always taken as they are
+    pub fn byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
     }
 
-    #[test]
-    fn test_sne_const_taken() {
-        let rom = vec![0x60, 0x12, 0x40, 0x13];
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x12);
+    pub fn random(mut self, get_random: impl Into<RandomSource>) -> Self {
+        self.get_random = get_random.into();
+        self
     }
 
-    #[test]
-    fn test_sne_const_not_taken() {
-        let rom = vec![0x60, 0x12, 0x40, 0x12];
+    pub fn build(self) -> Result<Rip8, Rip8Error> {
+        let memory = match self.program {
+            Program::Image(mut image) => {
+                if self.byte_order == ByteOrder::LittleEndian {
+                    image.chunks_exact_mut(2).for_each(|word| word.swap(0, 1));
+                }
+                image
+            },
+            Program::Rom(rom) => {
+                let memory_size = self.memory_model.memory_size();
+                if self.load_address < RIP8_ROM_START {
+                    return Err(Rip8Error::LoadAddressTooLow(self.load_address));
+                }
+                if rom.len() > memory_size.saturating_sub(self.load_address as usize) {
+                    return Err(Rip8Error::RomTooLarge(rom.len()));
+                }
 
-        let rip8 = run_rom(&rom);
+                let mut memory: Vec<u8> = Vec::with_capacity(memory_size);
+
+                // Fill reserved memory region
+                let font_len = RIP8_FONT.len().min(self.load_address as usize);
+                memory.extend_from_slice(&RIP8_FONT[..font_len]);
+                memory.resize(self.load_address as usize, 0xff);
+
+                // Copy rom code, pad as needed
+                memory.extend_from_slice(&rom);
+                memory.resize(memory_size, 0xff);
+                memory
+            },
+        };
+
+        let start_address = self.start_address.unwrap_or(self.load_address);
+        let mut rip8 = Rip8::try_from_memory(memory, self.freq, start_address, self.get_random)?;
+        rip8.quirks = self.quirks;
+        rip8.timing = self.timing;
+        rip8.init_registers();
+        Ok(rip8)
+    }
+}
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x12);
+impl Rip8 {
+    pub fn builder() -> Rip8Builder {
+        Rip8Builder::new()
     }
 
-    #[test]
-    fn test_se_reg_taken() {
-        let rom = vec![0x60, 0x12, 0x61, 0x12, 0x50, 0x10];
+    // Where every constructor ends up, `memory` is already laid out
+    fn try_from_memory(memory: Vec<u8>, freq: u32, start_address: u16, get_random: RandomSource) -> Result<Self, Rip8Error> {
+        // The memory model is implied by the image size
+        let memory_model = match MemoryModel::from_memory_size(memory.len()) {
+            Some(model) => model,
+            None => return Err(Rip8Error::ImageWrongSize(memory.len())),
+        };
+        if start_address as usize >= memory.len() {
+            return Err(Rip8Error::StartAddressOutOfRange(start_address));
+        }
 
-        let rip8 = run_rom(&rom);
+        Ok(Self {
+            pc: start_address,
+            memory: memory.clone(),
+            stack: Vec::with_capacity(RIP8_STACK_MAX_SIZE),
+            v: [0xff; 16],
+            i: 0xff,
+            display: vec![0x00; RIP8_HIRES_DISPLAY_SIZE * RIP8_DISPLAY_PLANE_COUNT],
+            keyboard: [false; RIP8_KEY_COUNT],
+            dt: 0x00,
+            st: 0x00,
+            rpl: [0x00; RIP8_RPL_FLAG_COUNT],
+            audio_pattern: None,
+            audio_pitch: 64,
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
-        assert_eq!(rip8.v[0x0], 0x12);
-        assert_eq!(rip8.v[0x1], 0x12);
+            start_address,
+            initial_memory: memory.clone(),
+            freq,
+            memory_model,
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            selected_planes: 0x1,
+            awaiting_input: false,
+            awaiter_index: 0,
+            released_keys: 0,
+            warn_uninitialized_reads: false,
+            written: 0,
+            awaiting_vblank: false,
+            elapsed: 0.0,
+            display_dirty: true,
+            hires: false,
+            resolution_changed: true,
+            get_random,
+            rewind_depth: 0,
+            rewind_deltas: VecDeque::new(),
+            trace_hook: None,
+            breakpoints: BTreeSet::new(),
+            resuming_from_breakpoint: false,
+            watchpoints: BTreeSet::new(),
+            watchpoint_hit: None,
+            watched_registers: 0,
+            memory_protection: false,
+            or_draw: false,
+            report_vblank_waits: false,
+            protection_fault: None,
+            instruction_budget: None,
+            budget_used: 0,
+            profiling: false,
+            opcode_counts: [0; PROFILE_SLOT_COUNT],
+            cycle_count: 0,
+            last_step_cost: 0,
+            decoded: vec![None; memory.len()],
+        })
     }
 
-    #[test]
-    fn test_se_reg_not_taken() {
-        let rom = vec![0x60, 0x12, 0x61, 0x13, 0x50, 0x10];
+    pub fn try_from_image_at_start(image: &[u8], freq: u32, start_address: u16, get_random: impl Into<RandomSource>) -> Result<Self, Rip8Error> {
+        Rip8Builder::new().image(image).load_address(start_address).freq(freq).random(get_random).build()
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn from_image_at_start(image: &[u8], freq: u32, start_address: u16, get_random: impl Into<RandomSource>) -> Self {
+        match Self::try_from_image_at_start(image, freq, start_address, get_random) {
+            Ok(rip8) => rip8,
+            Err(e) => panic!("{}", e),
+        }
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x12);
-        assert_eq!(rip8.v[0x1], 0x13);
+    pub fn from_image(image: &[u8], freq: u32, get_random: impl Into<RandomSource>) -> Self {
+        Self::from_image_at_start(image, freq, RIP8_ROM_START, get_random)
     }
 
-    #[test]
-    fn test_add_const() {
-        let rom = vec![0x60, 0x12, 0x70, 0x21];
+    pub fn try_from_rom_with_memory_model(rom: &[u8], freq: u32, loading_address: u16, memory_model: MemoryModel, get_random: impl Into<RandomSource>) -> Result<Self, Rip8Error> {
+        Rip8Builder::new().rom(rom).load_address(loading_address).memory_model(memory_model).freq(freq).random(get_random).build()
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn try_from_rom_at_address(rom: &[u8], freq: u32, loading_address: u16, get_random: impl Into<RandomSource>) -> Result<Self, Rip8Error> {
+        Self::try_from_rom_with_memory_model(rom, freq, loading_address, MemoryModel::Chip8, get_random)
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x33);
+    pub fn from_rom_at_address(rom: &[u8], freq: u32, loading_address: u16, get_random: impl Into<RandomSource>) -> Self {
+        match Self::try_from_rom_at_address(rom, freq, loading_address, get_random) {
+            Ok(rip8) => rip8,
+            Err(e) => panic!("{}", e),
+        }
     }
 
-    #[test]
-    fn test_add_const_overflow() {
-        let rom = vec![0x60, 0xff, 0x70, 0x01];
+    pub fn from_rom(rom: &[u8], freq: u32, get_random: impl Into<RandomSource>) -> Self {
+        Self::from_rom_at_address(rom, freq, RIP8_ROM_START, get_random)
+    }
 
-        let rip8 = run_rom(&rom);
+    pub fn with_random(mut self, get_random: impl Into<RandomSource>) -> Self {
+        self.get_random = get_random.into();
+        self
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x00);
+    // Snapshots the whole machine, see the format description above.
+    // Breakpoints, hooks and rewind history are not part of the state
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(RIP8_SAVE_STATE_MAGIC);
+        bytes.push(RIP8_SAVE_STATE_VERSION);
+        bytes.extend_from_slice(&self.pc.to_be_bytes());
+        bytes.extend_from_slice(&self.i.to_be_bytes());
+        bytes.extend_from_slice(&self.v);
+        bytes.push(self.dt);
+        bytes.push(self.st);
+        bytes.push(self.stack.len() as u8);
+        for addr in self.stack.iter() {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+        bytes.push(match self.memory_model {
+            MemoryModel::Chip8 => 0,
+            MemoryModel::XoChip => 1,
+        });
+        bytes.extend_from_slice(&self.memory);
+        bytes.extend_from_slice(&self.initial_memory);
+        bytes.extend_from_slice(&self.display);
+        bytes.push(self.hires as u8);
+        bytes.extend(self.keyboard.iter().map(|k| *k as u8));
+        bytes.push(self.awaiting_input as u8);
+        bytes.push(self.awaiter_index as u8);
+        bytes.push(self.awaiting_vblank as u8);
+        bytes.extend_from_slice(&self.released_keys.to_be_bytes());
+        bytes.extend_from_slice(&self.rpl);
+        bytes.push(self.audio_pattern.is_some() as u8);
+        bytes.extend_from_slice(&self.audio_pattern.unwrap_or([0x00; RIP8_AUDIO_PATTERN_SIZE]));
+        bytes.push(self.audio_pitch);
+        bytes.push(self.selected_planes);
+        bytes.extend_from_slice(&self.start_address.to_be_bytes());
+        bytes.extend_from_slice(&self.freq.to_be_bytes());
+        bytes.extend_from_slice(&self.elapsed.to_bits().to_be_bytes());
+        let quirks = [
+            self.quirks.shift_uses_vy,
+            self.quirks.load_store_increments_i,
+            self.quirks.jump_with_vx,
+            self.quirks.logic_resets_vf,
+            self.quirks.clip_sprites,
+            self.quirks.display_wait,
+            self.quirks.count_collided_rows,
+            self.quirks.zero_registers,
+            self.quirks.trap_sys,
+            self.quirks.i_overflow_sets_vf,
+            self.quirks.large_sprites,
+        ];
+        let quirks = quirks.iter().enumerate().fold(0u16, |acc, (bit, set)| acc | ((*set as u16) << bit));
+        bytes.extend_from_slice(&quirks.to_be_bytes());
+        bytes
+    }
+
+    // Restores a machine out of `serialize`'s output, the random number
+    // source can't be saved so it has to be supplied again
+    pub fn deserialize(bytes: &[u8], get_random: impl Into<RandomSource>) -> Result<Self, Rip8Error> {
+        let mut reader = SaveStateReader { bytes, pos: 0 };
+        if reader.take(RIP8_SAVE_STATE_MAGIC.len()).map_err(|_| Rip8Error::SaveStateBadMagic)? != RIP8_SAVE_STATE_MAGIC {
+            return Err(Rip8Error::SaveStateBadMagic);
+        }
+        let version = reader.byte()?;
+        if version != RIP8_SAVE_STATE_VERSION {
+            return Err(Rip8Error::SaveStateVersion(version));
+        }
+
+        let pc = reader.word()?;
+        let i = reader.word()?;
+        let mut v = [0x00; 16];
+        v.copy_from_slice(reader.take(16)?);
+        let dt = reader.byte()?;
+        let st = reader.byte()?;
+        let stack_len = reader.byte()? as usize;
+        if stack_len > RIP8_STACK_MAX_SIZE {
+            return Err(Rip8Error::SaveStateCorrupt);
+        }
+        let mut stack = Vec::with_capacity(RIP8_STACK_MAX_SIZE);
+        for _ in 0..stack_len {
+            stack.push(reader.word()?);
+        }
+        let memory_model = match reader.byte()? {
+            0 => MemoryModel::Chip8,
+            1 => MemoryModel::XoChip,
+            _ => return Err(Rip8Error::SaveStateCorrupt),
+        };
+        let memory = reader.take(memory_model.memory_size())?.to_vec();
+        let initial_memory = reader.take(memory_model.memory_size())?.to_vec();
+        let display = reader.take(RIP8_HIRES_DISPLAY_SIZE * RIP8_DISPLAY_PLANE_COUNT)?.to_vec();
+        let hires = reader.flag()?;
+        let mut keyboard = [false; RIP8_KEY_COUNT];
+        for key in keyboard.iter_mut() {
+            *key = reader.flag()?;
+        }
+        let awaiting_input = reader.flag()?;
+        let awaiter_index = reader.byte()? as usize;
+        if awaiter_index >= 16 {
+            return Err(Rip8Error::SaveStateCorrupt);
+        }
+        let awaiting_vblank = reader.flag()?;
+        let released_keys = reader.word()?;
+        let mut rpl = [0x00; RIP8_RPL_FLAG_COUNT];
+        rpl.copy_from_slice(reader.take(RIP8_RPL_FLAG_COUNT)?);
+        let has_audio_pattern = reader.flag()?;
+        let mut audio_pattern = [0x00; RIP8_AUDIO_PATTERN_SIZE];
+        audio_pattern.copy_from_slice(reader.take(RIP8_AUDIO_PATTERN_SIZE)?);
+        let audio_pitch = reader.byte()?;
+        let selected_planes = reader.byte()?;
+        let start_address = reader.word()?;
+        let freq = reader.dword()?;
+        let elapsed = f64::from_bits(reader.qword()?);
+        let quirks = reader.word()?;
+
+        let mut rip8 = Self::try_from_image_at_start(&initial_memory, freq, start_address, get_random)?;
+        rip8.pc = pc;
+        rip8.i = i;
+        rip8.v = v;
+        rip8.dt = dt;
+        rip8.st = st;
+        rip8.stack = stack;
+        rip8.memory = memory;
+        rip8.decoded.fill(None);
+        rip8.display = display;
+        rip8.hires = hires;
+        rip8.keyboard = keyboard;
+        rip8.awaiting_input = awaiting_input;
+        rip8.awaiter_index = awaiter_index;
+        rip8.awaiting_vblank = awaiting_vblank;
+        rip8.released_keys = released_keys;
+        rip8.rpl = rpl;
+        rip8.audio_pattern = if has_audio_pattern { Some(audio_pattern) } else { None };
+        rip8.audio_pitch = audio_pitch;
+        rip8.selected_planes = selected_planes;
+        rip8.elapsed = elapsed;
+        rip8.quirks = Quirks {
+            shift_uses_vy: quirks & 0x01 != 0,
+            load_store_increments_i: quirks & 0x02 != 0,
+            jump_with_vx: quirks & 0x04 != 0,
+            logic_resets_vf: quirks & 0x08 != 0,
+            clip_sprites: quirks & 0x10 != 0,
+            display_wait: quirks & 0x20 != 0,
+            count_collided_rows: quirks & 0x40 != 0,
+            zero_registers: quirks & 0x80 != 0,
+            trap_sys: quirks & 0x100 != 0,
+            i_overflow_sets_vf: quirks & 0x200 != 0,
+            large_sprites: quirks & 0x400 != 0,
+        };
+        Ok(rip8)
+    }
+
+    // Brings the machine back to the state it was right after construction.
+    // Configuration (quirks, breakpoints, hooks) and the RPL user flags,
+    // which are meant to survive between runs, are kept
+    pub fn reset(&mut self) {
+        self.pc = self.start_address;
+        self.memory.copy_from_slice(&self.initial_memory);
+        self.decoded.fill(None);
+        self.stack.clear();
+        self.init_registers();
+        self.written = 0;
+        for byte in self.display.iter_mut() {
+            *byte = 0x00;
+        }
+        self.display_dirty = true;
+        if self.hires {
+            self.hires = false;
+            self.resolution_changed = true;
+        }
+        self.keyboard = [false; RIP8_KEY_COUNT];
+        self.dt = 0x00;
+        self.st = 0x00;
+        self.audio_pattern = None;
+        self.audio_pitch = 64;
+        self.selected_planes = 0x1;
+        self.awaiting_input = false;
+        self.awaiter_index = 0;
+        self.awaiting_vblank = false;
+        self.elapsed = 0.0;
+        self.rewind_deltas.clear();
+        self.resuming_from_breakpoint = false;
+        self.watchpoint_hit = None;
+        self.protection_fault = None;
+        self.budget_used = 0;
+        self.opcode_counts = [0; PROFILE_SLOT_COUNT];
+        self.cycle_count = 0;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    pub fn get_timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn get_memory_model(&self) -> MemoryModel {
+        self.memory_model
+    }
+
+    // Shorthand for switching between the S-CHIP preset and the defaults
+    pub fn set_s_chip_mode(&mut self, s_chip_mode: bool) {
+        self.quirks = if s_chip_mode { Quirks::schip() } else { Quirks::default() };
+    }
+
+    // Clears the screen, timers and keyboard but keeps registers and memory
+    // intact, unlike a full reset
+    pub fn warm_reset(&mut self) {
+        for byte in self.display.iter_mut() {
+            *byte = 0x00;
+        }
+        self.display_dirty = true;
+        self.dt = 0x00;
+        self.st = 0x00;
+        self.elapsed = 0.0;
+        self.keyboard = [false; RIP8_KEY_COUNT];
+    }
+
+    fn init_registers(&mut self) {
+        let value = if self.quirks.zero_registers { 0x00 } else { 0xff };
+        self.v = [value; 16];
+        self.i = value as u16;
+    }
+
+    // When enabled, trace events flag the registers an instruction reads
+    // before anything wrote them. Registers are only tracked from then on,
+    // so turn it on before running anything
+    pub fn set_warn_uninitialized_reads(&mut self, warn: bool) {
+        self.warn_uninitialized_reads = warn;
+        self.written = 0;
+    }
+
+    // V registers an instruction reads and writes, one bit per register
+    fn register_usage(&self, instruction: Instruction) -> (u16, u16) {
+        let vx = |x: usize| -> u16 { 1 << x };
+        let up_to = |x: usize| -> u16 { ((1u32 << (x + 1)) - 1) as u16 };
+        match instruction {
+            Instruction::SeVxKk(x, _) | Instruction::SneVxKk(x, _) | Instruction::Skp(x) |
+            Instruction::Sknp(x) | Instruction::LdDtVx(x) | Instruction::LdStVx(x) |
+            Instruction::LdFVx(x) | Instruction::LdBVx(x) | Instruction::Pitch(x) => (vx(x), 0),
+            Instruction::AddIVx(x) => {
+                let vf = if self.quirks.i_overflow_sets_vf { vx(0xf) } else { 0 };
+                (vx(x), vf)
+            }
+            Instruction::SeVxVy(x, y) | Instruction::SneVxVy(x, y) => (vx(x) | vx(y), 0),
+            Instruction::LdVxKk(x, _) | Instruction::Rnd(x, _) | Instruction::LdVxDt(x) => (0, vx(x)),
+            Instruction::AddVxKk(x, _) => (vx(x), vx(x)),
+            Instruction::LdVxVy(x, y) => (vx(y), vx(x)),
+            Instruction::Or(x, y) | Instruction::And(x, y) | Instruction::Xor(x, y) => {
+                let vf = if self.quirks.logic_resets_vf { vx(0xf) } else { 0 };
+                (vx(x) | vx(y), vx(x) | vf)
+            }
+            Instruction::AddVxVy(x, y) | Instruction::Sub(x, y) | Instruction::Subn(x, y) => {
+                (vx(x) | vx(y), vx(x) | vx(0xf))
+            }
+            Instruction::Shr(x, y) | Instruction::Shl(x, y) => {
+                let o = if self.quirks.shift_uses_vy { y } else { x };
+                (vx(o), vx(x) | vx(0xf))
+            }
+            Instruction::JpV0(addr) => {
+                let o = if self.quirks.jump_with_vx { (addr >> 8) as usize } else { 0 };
+                (vx(o), 0)
+            }
+            Instruction::Drw(x, y, _) => (vx(x) | vx(y), vx(0xf)),
+            Instruction::LdIVx(x) | Instruction::LdRVx(x) => (up_to(x), 0),
+            Instruction::LdVxI(x) | Instruction::LdVxR(x) => (0, up_to(x)),
+            Instruction::SaveVxVy(x, y) => (up_to(x.max(y)) & !(up_to(x.min(y)) >> 1), 0),
+            Instruction::LoadVxVy(x, y) => (0, up_to(x.max(y)) & !(up_to(x.min(y)) >> 1)),
+            // Fx0A's register gets written once the wait is over
+            _ => (0, 0),
+        }
+    }
+
+    // The hook gets called for every instruction before it's executed
+    pub fn set_trace_hook(&mut self, hook: Box<dyn FnMut(TraceEvent)>) {
+        self.trace_hook = Some(hook);
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    // Out of range registers read as 0
+    pub fn get_register(&self, idx: usize) -> u8 {
+        if idx < 0x10 {
+            self.v[idx]
+        } else {
+            0
+        }
+    }
+
+    // Writes to out of range registers are ignored
+    pub fn set_register(&mut self, idx: usize, val: u8) {
+        if idx < 0x10 {
+            self.v[idx] = val;
+        }
+    }
+
+    pub fn get_i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Return addresses of the subroutines being run, outermost first
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.stack.clone()
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    // The opcode `step` would execute next, nothing changes by peeking
+    pub fn peek_opcode(&self) -> u16 {
+        u16::from_be_bytes([self.mem(self.pc), self.mem(self.pc.wrapping_add(1))])
+    }
+
+    // Same as `peek_opcode`, disassembled. F000 shows the address that
+    // follows it
+    pub fn peek_disassembly(&self) -> String {
+        match self.peek_opcode() {
+            0xf000 => {
+                let addr = u16::from_be_bytes([self.mem(self.pc.wrapping_add(2)), self.mem(self.pc.wrapping_add(3))]);
+                format!("LD I, 0x{:04X}", addr)
+            }
+            ir => disassemble(ir),
+        }
+    }
+
+    // Any address is accepted, but keep in mind that instructions are
+    // expected to be aligned and that moving the PC to an odd address will
+    // make it fetch instructions out of step
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    // How far along the way to the next 60hz tick time is, from 0.0 (right
+    // after a tick) to just under 1.0. Together with the timers, this tells
+    // exactly how long until they run out
+    pub fn get_timer_fraction(&self) -> f64 {
+        self.elapsed
+    }
+
+    pub fn get_cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    // Machine cycles (see `Timing`) the last call to `step` took, steps that
+    // didn't execute anything (stalled or stopped at a breakpoint) take none
+    pub fn get_last_step_cost(&self) -> u32 {
+        self.last_step_cost
+    }
+
+    // Addresses past the end of memory wrap around
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.mem(addr)
+    }
+
+    // Addresses past the end of memory wrap around. Anything can be written,
+    // including the reserved region below RIP8_ROM_START, but keep in mind
+    // that overwriting the font data there will break Fx29
+    pub fn write_memory(&mut self, addr: u16, val: u8) {
+        let idx = self.mem_index(addr);
+        self.memory[idx] = val;
+        self.invalidate_decoded(idx);
+    }
+
+    // Copies `bytes` into memory starting at `addr`, refusing (and writing
+    // nothing) if they don't fit before the end of memory. Same as
+    // `write_memory`, `reset` brings back the memory as it was loaded
+    pub fn load_segment(&mut self, bytes: &[u8], addr: u16) -> Result<(), Rip8Error> {
+        let start = addr as usize;
+        if bytes.len() > self.memory.len().saturating_sub(start) {
+            return Err(Rip8Error::SegmentOutOfRange(addr, bytes.len()));
+        }
+        for (offset, val) in bytes.iter().enumerate() {
+            self.write_memory((start + offset) as u16, *val);
+        }
+        Ok(())
+    }
+
+    // The whole memory as it is now, writes included, in the format
+    // `from_image` loads (so starting it at `get_pc` picks up from here)
+    pub fn dump_image(&self) -> Vec<u8> {
+        self.memory.clone()
+    }
+
+    // Up to `len` bytes starting at `start`, cut short at the end of memory
+    pub fn memory_slice(&self, start: u16, len: usize) -> &[u8] {
+        let start = (start as usize).min(self.memory.len());
+        let end = start.saturating_add(len).min(self.memory.len());
+        &self.memory[start..end]
+    }
+
+    // `step` stops right before fetching an instruction from a breakpoint's
+    // address, the next call to `step` resumes execution from there
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // Debugging aid for ROM authors: when enabled, the program's writes
+    // below RIP8_ROM_START (where the font lives) are dropped and `step`
+    // returns `ProtectionFault` once the instruction completes. Off by
+    // default, the reserved region is writable on real hardware
+    pub fn set_memory_protection(&mut self, protect: bool) {
+        self.memory_protection = protect;
+    }
+
+    // Debugging aid for laying out sprites: when enabled, Dxyn ORs sprites
+    // onto the display instead of XORing them, so overlapping draws pile up
+    // rather than erase each other. VF still reports collisions as usual.
+    // Real CHIP-8 always XORs, so it's off by default
+    pub fn set_or_draw(&mut self, or_draw: bool) {
+        self.or_draw = or_draw;
+    }
+
+    pub fn get_or_draw(&self) -> bool {
+        self.or_draw
+    }
+
+    // Under the display wait quirk, a Dxyn makes the machine wait for the
+    // next vertical blank, which is what paces drawing (and flicker) to one
+    // sprite per frame on the COSMAC VIP. When enabled, the step drawing
+    // that sprite returns `VblankWait` (with the sprite already drawn) so
+    // frontends know to present the display now and run nothing else until
+    // the next frame. The wait is over once `tick_timers` completes a 60Hz
+    // tick, calls adding up to less than that don't release it. Otherwise
+    // waiting only shows through `is_awaiting_vblank`, and a zero
+    // `get_last_step_cost` for the steps that wait
+    pub fn set_report_vblank_waits(&mut self, report: bool) {
+        self.report_vblank_waits = report;
+    }
+
+    // `step` returns `WatchpointHit` once an instruction writes to a
+    // watched address, after the instruction completes
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.remove(&addr);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // `step` returns `RegisterChanged` once an instruction leaves a watched
+    // V register with a different value. Any change counts, including VF
+    // being set as a flag by arithmetic, shifts or Dxyn. Out of range
+    // registers are ignored
+    pub fn watch_register(&mut self, idx: usize) {
+        if idx < 0x10 {
+            self.watched_registers |= 1 << idx;
+        }
+    }
+
+    pub fn unwatch_register(&mut self, idx: usize) {
+        if idx < 0x10 {
+            self.watched_registers &= !(1 << idx);
+        }
+    }
+
+    // Counts every instruction fetched by kind while enabled, turning it on
+    // starts counting from zero
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+        if profiling {
+            self.opcode_counts = [0; PROFILE_SLOT_COUNT];
+        }
+    }
+
+    // How many instructions of each kind ran while profiling, by opcode
+    // pattern (such as "8xy4" or "Dxyn"). Kinds that never ran are left out
+    pub fn opcode_histogram(&self) -> BTreeMap<&'static str, u64> {
+        PROFILE_PATTERNS.iter().zip(self.opcode_counts.iter())
+            .filter(|(_, count)| **count > 0)
+            .map(|(pattern, count)| (*pattern, *count))
+            .collect()
+    }
+
+    // Safety valve for tools running ROMs that may never stop: once `budget`
+    // instructions run without `tick_timers` being called in between, `step`
+    // returns `BudgetExceeded` (executing nothing) until it is. Time going
+    // by inside `run_until_halt` and `step_until_draw` doesn't count, those
+    // get `budget` instructions per call instead. `None`, the default, means
+    // no limit
+    pub fn set_instruction_budget(&mut self, budget: Option<u32>) {
+        self.instruction_budget = budget;
+        self.budget_used = 0;
+    }
+
+    // Keep enough information to undo the last `depth` steps, 0 disables it
+    pub fn enable_delta_rewind(&mut self, depth: usize) {
+        self.rewind_depth = depth;
+        while self.rewind_deltas.len() > depth {
+            self.rewind_deltas.pop_front();
+        }
+    }
+
+    // Undoes the last step, returns false if there's nothing left to undo
+    pub fn step_back(&mut self) -> bool {
+        let delta = match self.rewind_deltas.pop_back() {
+            Some(delta) => delta,
+            None => return false,
+        };
+
+        for (addr, val) in delta.memory.into_iter().rev() {
+            self.memory[addr] = val;
+            self.invalidate_decoded(addr);
+        }
+        for (idx, val) in delta.display.into_iter().rev() {
+            self.display[idx] = val;
+            self.display_dirty = true;
+        }
+        self.stack.truncate(delta.stack_len);
+        if let Some(addr) = delta.stack_top {
+            if self.stack.len() < delta.stack_len {
+                self.stack.push(addr);
+            }
+        }
+
+        self.pc = delta.pc;
+        self.v = delta.v;
+        self.i = delta.i;
+        self.dt = delta.dt;
+        self.st = delta.st;
+        self.rpl = delta.rpl;
+        self.audio_pattern = delta.audio_pattern;
+        self.audio_pitch = delta.audio_pitch;
+        self.selected_planes = delta.selected_planes;
+        if self.hires != delta.hires {
+            self.hires = delta.hires;
+            self.resolution_changed = true;
+        }
+        self.awaiting_input = delta.awaiting_input;
+        self.awaiter_index = delta.awaiter_index;
+        self.released_keys = delta.released_keys;
+        self.awaiting_vblank = delta.awaiting_vblank;
+        self.elapsed = delta.elapsed;
+        true
+    }
+
+    fn record_rewind_delta(&mut self) {
+        if self.rewind_deltas.len() == self.rewind_depth {
+            self.rewind_deltas.pop_front();
+        }
+        // Any step can pop at most one return address
+        self.rewind_deltas.push_back(RewindDelta {
+            pc: self.pc,
+            stack_len: self.stack.len(),
+            stack_top: self.stack.last().copied(),
+            v: self.v,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            rpl: self.rpl,
+            audio_pattern: self.audio_pattern,
+            audio_pitch: self.audio_pitch,
+            selected_planes: self.selected_planes,
+            hires: self.hires,
+            awaiting_input: self.awaiting_input,
+            awaiter_index: self.awaiter_index,
+            released_keys: self.released_keys,
+            awaiting_vblank: self.awaiting_vblank,
+            elapsed: self.elapsed,
+            memory: Vec::new(),
+            display: Vec::new(),
+        });
+    }
+
+    // Every memory access goes through here, addresses past the end of memory
+    // wrap around. Only the 4KB model can be overrun, since any u16 fits
+    // within the 64KB one
+    fn mem_index(&self, addr: u16) -> usize {
+        addr as usize % self.memory.len()
+    }
+
+    fn mem(&self, addr: u16) -> u8 {
+        self.memory[self.mem_index(addr)]
+    }
+
+    // Moves the program counter forward, wrapping around at the end of
+    // memory so that it always holds a valid address
+    fn advance_pc(&mut self, by: u16) {
+        self.pc = self.mem_index(self.pc.wrapping_add(by)) as u16;
+    }
+
+    // Skips over the next instruction, XO-CHIP's F000 NNNN long load takes
+    // 4 bytes rather than 2
+    fn skip_next(&mut self) {
+        let by = if self.peek_opcode() == 0xf000 { 4 } else { 2 };
+        self.advance_pc(by);
+    }
+
+    // All memory writes done by instructions should go through here so that
+    // they can be undone and watched (which is why there's no plain `&mut`
+    // accessor)
+    fn store(&mut self, addr: u16, val: u8) {
+        let addr = self.mem_index(addr);
+        if self.memory_protection && addr < RIP8_ROM_START as usize {
+            self.protection_fault.get_or_insert(addr as u16);
+            return;
+        }
+        if self.rewind_depth > 0 {
+            if let Some(delta) = self.rewind_deltas.back_mut() {
+                delta.memory.push((addr, self.memory[addr]));
+            }
+        }
+        if self.watchpoint_hit.is_none() && self.watchpoints.contains(&(addr as u16)) {
+            self.watchpoint_hit = Some((addr as u16, self.memory[addr], val));
+        }
+        self.memory[addr] = val;
+        self.invalidate_decoded(addr);
+    }
+
+    // A byte belongs to the instruction starting on it and to the one
+    // starting right before it, both have to be decoded again
+    fn invalidate_decoded(&mut self, idx: usize) {
+        // Deserialized machines start out without a cache
+        if self.decoded.len() != self.memory.len() {
+            return;
+        }
+        let prev = (idx + self.memory.len() - 1) % self.memory.len();
+        self.decoded[idx] = None;
+        self.decoded[prev] = None;
+    }
+
+    // Fetches the instruction at `pc` (without moving it), decoding it
+    // unless it was already decoded since the last write to it
+    fn fetch(&mut self) -> (u16, Instruction) {
+        if self.decoded.len() != self.memory.len() {
+            self.decoded = vec![None; self.memory.len()];
+        }
+        let idx = self.mem_index(self.pc);
+        if let Some(cached) = self.decoded[idx] {
+            return cached;
+        }
+        let ir = u16::from_be_bytes([self.mem(self.pc), self.mem(self.pc.wrapping_add(1))]);
+        let decoded = (ir, decode(ir));
+        self.decoded[idx] = Some(decoded);
+        decoded
+    }
+
+    // Same as `store`, but for the display
+    fn set_display_byte(&mut self, idx: usize, val: u8) {
+        if self.display[idx] == val {
+            return;
+        }
+        if self.rewind_depth > 0 {
+            if let Some(delta) = self.rewind_deltas.back_mut() {
+                delta.display.push((idx, self.display[idx]));
+            }
+        }
+        self.display[idx] = val;
+        self.display_dirty = true;
+    }
+
+    pub fn set_keydown(&mut self, k: usize, v: bool) {
+        if k < 0x10 {
+            // Handling keydown events is a bit involved because of the fx0a
+            // instruction, for more information see:
+            // https://retrocomputing.stackexchange.com/a/361
+            // Any key held while waiting counts, even if it was already down
+            // when the wait started. Releases are only collected here, the
+            // wait is completed by the next `step` so that keys released
+            // together (between two steps) resolve to the lowest one
+            if self.keyboard[k] && !v && self.awaiting_input {
+                self.released_keys |= 1 << k;
+            }
+            self.keyboard[k] = v;
+        }
+    }
+
+    // Whether the interpreter believes key `k` is down, out of range keys
+    // never are
+    pub fn is_keydown(&self, k: usize) -> bool {
+        k < RIP8_KEY_COUNT && self.keyboard[k]
+    }
+
+    pub fn keyboard_state(&self) -> [bool; RIP8_KEY_COUNT] {
+        self.keyboard
+    }
+
+    // Gives up on an ongoing Fx0A, execution resumes right after it and the
+    // register it was waiting to load keeps whatever value it had
+    pub fn cancel_input_wait(&mut self) {
+        self.awaiting_input = false;
+    }
+
+    pub fn get_display_spot(&self, x: usize, y: usize) -> bool {
+        self.get_display_pixel(x, y) != 0
+    }
+
+    // Returns the value of the spot on each plane, bit 0 being the first
+    // plane and bit 1 being the second one (only XO-CHIP ROMs use the latter)
+    pub fn get_display_pixel(&self, mut x: usize, mut y: usize) -> u8 {
+        let (width, height) = self.framebuffer_dimensions();
+        x %= width;
+        y %= height;
+        let mut pixel = 0;
+        for plane in 0..RIP8_DISPLAY_PLANE_COUNT {
+            let (idx, mask) = self.spot_location(plane, x, y);
+            if self.display[idx] & mask != 0 {
+                pixel |= 1 << plane;
+            }
+        }
+        pixel
+    }
+
+    // Whether the display changed since the last call (or since construction
+    // for the first one), so that frontends can skip redrawing it
+    pub fn take_display_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.display_dirty)
+    }
+
+    // Whether the program switched to S-CHIP's 128x64 hires mode (00FF),
+    // every program starts out in the regular 64x32 one (00FE)
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // Whether `framebuffer_dimensions` changed since the last call (or since
+    // construction for the first one), so that frontends know when to resize
+    // whatever they draw the display into
+    pub fn take_resolution_changed(&mut self) -> bool {
+        core::mem::take(&mut self.resolution_changed)
+    }
+
+    // The first display plane, bit-packed in row-major order with the leftmost
+    // spot of each byte in the MSB (RIP8_DISPLAY_SIZE bytes in total, or
+    // RIP8_HIRES_DISPLAY_SIZE in hires)
+    pub fn framebuffer(&self) -> &[u8] {
+        self.plane_framebuffer(0)
+    }
+
+    // Same layout as `framebuffer`, for any of the XO-CHIP planes
+    pub fn plane_framebuffer(&self, plane: usize) -> &[u8] {
+        let start = plane * RIP8_HIRES_DISPLAY_SIZE;
+        &self.display[start..start + self.plane_size()]
+    }
+
+    // Width and height (in spots) of the framebuffer
+    pub fn framebuffer_dimensions(&self) -> (usize, usize) {
+        if self.hires {
+            (RIP8_HIRES_DISPLAY_WIDTH, RIP8_HIRES_DISPLAY_HEIGHT)
+        } else {
+            (RIP8_DISPLAY_WIDTH, RIP8_DISPLAY_HEIGHT)
+        }
+    }
+
+    // Bytes of each plane the current resolution uses
+    fn plane_size(&self) -> usize {
+        let (width, height) = self.framebuffer_dimensions();
+        width * height / 8
+    }
+
+    // Hash of every plane along with the framebuffer dimensions, the same
+    // screen gives the same hash on any platform, so whole screens can be
+    // compared against a recorded value
+    pub fn display_hash(&self) -> u64 {
+        let (width, height) = self.framebuffer_dimensions();
+        let mut bytes = Vec::with_capacity(4 + self.display.len());
+        bytes.extend_from_slice(&(width as u16).to_be_bytes());
+        bytes.extend_from_slice(&(height as u16).to_be_bytes());
+        for plane in 0..RIP8_DISPLAY_PLANE_COUNT {
+            bytes.extend_from_slice(self.plane_framebuffer(plane));
+        }
+        fnv1a(&bytes)
+    }
+
+    // Renders the display as a tightly-packed RGBA buffer, each spot becoming
+    // a `scale` x `scale` square of its color in `palette`, which is indexed
+    // like `get_display_pixel` (programs using a single plane only need the
+    // first two). Returns the width and height of the image along with the
+    // buffer
+    pub fn to_rgba(&self, palette: &[[u8; 4]; 4], scale: usize) -> (u32, u32, Vec<u8>) {
+        let (width, height) = self.framebuffer_dimensions();
+        let mut rgba = Vec::with_capacity(width * height * scale * scale * 4);
+        for y in 0..height * scale {
+            for x in 0..width * scale {
+                let color = palette[self.get_display_pixel(x / scale, y / scale) as usize];
+                rgba.extend_from_slice(&color);
+            }
+        }
+        ((width * scale) as u32, (height * scale) as u32, rgba)
+    }
+
+    // Renders the display as text, one line per row (each ending with a
+    // newline) using `on` for spots lit on any plane and `off` for the rest
+    pub fn to_ascii(&self, on: char, off: char) -> String {
+        let (width, height) = self.framebuffer_dimensions();
+        let mut ascii = String::with_capacity((width + 1) * height);
+        for y in 0..height {
+            for x in 0..width {
+                ascii.push(if self.get_display_spot(x, y) { on } else { off });
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    // Lists the (x, y) coordinates of every spot that's on, row by row
+    pub fn lit_pixels(&self) -> Vec<(usize, usize)> {
+        let (width, height) = self.framebuffer_dimensions();
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if self.get_display_spot(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    // True while Fx0A waits for a key to be pressed and released, the wait
+    // only ends with the `step` following the release
+    pub fn is_awaiting_input(&self) -> bool {
+        self.awaiting_input
+    }
+
+    // True when a sprite was just drawn under the display wait quirk, no
+    // further instructions will run until the timers tick again
+    pub fn is_awaiting_vblank(&self) -> bool {
+        self.awaiting_vblank
+    }
+
+    pub fn is_tone_on(&self) -> bool {
+        self.st != 0
+    }
+
+    // The 128 1-bit samples an XO-CHIP ROM asked to play (MSB first), or
+    // None if the classic buzzer tone should be used instead
+    pub fn get_audio_pattern(&self) -> Option<[u8; RIP8_AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern
+    }
+
+    pub fn get_audio_pitch(&self) -> u8 {
+        self.audio_pitch
+    }
+
+    // Rate (in samples per second) at which the audio pattern should be
+    // played, as derived from the pitch by the XO-CHIP spec
+    pub fn get_audio_playback_rate(&self) -> f32 {
+        4000.0 * libm::powf(2.0, (self.audio_pitch as f32 - 64.0) / 48.0)
+    }
+
+    fn spot_location(&self, plane: usize, x: usize, y: usize) -> (usize, u8) {
+        let (width, _) = self.framebuffer_dimensions();
+        let bit = y * width + x;
+        (plane * RIP8_HIRES_DISPLAY_SIZE + bit / 8, 0x80 >> (bit % 8))
+    }
+
+    fn set_spot(&mut self, plane: usize, mut x: usize, mut y: usize, val: bool) -> bool {
+        let (width, height) = self.framebuffer_dimensions();
+        x %= width;
+        y %= height;
+        let (idx, mask) = self.spot_location(plane, x, y);
+        let unset = self.display[idx] & mask != 0 && val;
+        if val {
+            let byte = if self.or_draw { self.display[idx] | mask } else { self.display[idx] ^ mask };
+            self.set_display_byte(idx, byte);
+        }
+        unset
+    }
+
+    // Bytes per row and rows of the sprite Dxyn draws, n == 0 being the
+    // special case: S-CHIP draws 16x16 sprites in hires, and 8x16 ones in
+    // lores under the `large_sprites` quirk. CHIP-8 draws nothing
+    fn sprite_shape(&self, n: u8) -> (usize, usize) {
+        match n {
+            0 if self.is_hires() => (2, 16),
+            0 if self.quirks.large_sprites => (1, 16),
+            n => (1, n as usize),
+        }
+    }
+
+    // Draws a sprite row one spot at a time, so that whatever falls off the
+    // right edge is either dropped or wrapped to the start of the same row
+    // (per the clip quirk), and collisions only count for spots drawn
+    fn set_spot_byte(&mut self, plane: usize, mut x: usize, y: usize, byte: u8) -> bool {
+        let (width, _) = self.framebuffer_dimensions();
+        let mut unset = false;
+        x %= width;
+        for s in 0..8 {
+            if self.quirks.clip_sprites && x + s >= width {
+                break;
+            }
+            let spot = ((byte >> (7-s)) & 0x01) != 0x00;
+            unset |= self.set_spot(plane, x + s, y, spot);
+        }
+        unset
+    }
+
+    // Clears the selected planes only, as per XO-CHIP semantics
+    fn clear_display(&mut self) {
+        let size = self.plane_size();
+        for plane in 0..RIP8_DISPLAY_PLANE_COUNT {
+            if self.selected_planes & (1 << plane) != 0 {
+                let start = plane * RIP8_HIRES_DISPLAY_SIZE;
+                for idx in start..start + size {
+                    self.set_display_byte(idx, 0x00);
+                }
+            }
+        }
+    }
+
+    // Scrolls the selected planes up, the bottom rows are left blank
+    fn scroll_up(&mut self, rows: usize) {
+        let (width, height) = self.framebuffer_dimensions();
+        let size = self.plane_size();
+        let row_size = width / 8;
+        let shift = rows.min(height) * row_size;
+        for plane in 0..RIP8_DISPLAY_PLANE_COUNT {
+            if self.selected_planes & (1 << plane) != 0 {
+                let start = plane * RIP8_HIRES_DISPLAY_SIZE;
+                for idx in start..start + size {
+                    let val = if idx + shift < start + size {
+                        self.display[idx + shift]
+                    } else {
+                        0x00
+                    };
+                    self.set_display_byte(idx, val);
+                }
+            }
+        }
+    }
+
+    // S-CHIP's 00FE and 00FF. Every plane is cleared, as Octo does, since
+    // whatever was drawn wouldn't make sense at the other resolution
+    fn set_hires(&mut self, hires: bool) {
+        for idx in 0..self.display.len() {
+            self.set_display_byte(idx, 0x00);
+        }
+        if self.hires != hires {
+            self.hires = hires;
+            self.resolution_changed = true;
+        }
+    }
+
+    // Lets `delta_seconds` go by, counting the timers down at 60hz. Each
+    // tick is also a vertical blank, which releases the display wait.
+    // Timers only move here, never in `step`, so they keep counting down
+    // while Fx0A waits for a key as long as this keeps being called
+    pub fn tick_timers(&mut self, delta_seconds: f64) {
+        self.budget_used = 0;
+        self.advance_time(delta_seconds);
+    }
+
+    fn advance_time(&mut self, delta_seconds: f64) {
+        self.elapsed += delta_seconds * 60.0;
+        while self.elapsed >= 1.0 {
+            self.dt = self.dt.saturating_sub(1);
+            self.st = self.st.saturating_sub(1);
+            self.elapsed -= 1.0;
+            self.awaiting_vblank = false;
+        }
+    }
+
+    // Executes a single instruction, no time goes by (see `tick_timers`)
+    pub fn step(&mut self) -> StepOutcome {
+        self.last_step_cost = 0;
+        // Registers are compared once the instruction is done, only worth
+        // a copy when some are watched
+        let v_before = if self.watched_registers != 0 { Some(self.v) } else { None };
+        // Ending Fx0A's wait is part of this step, so undoing the step has to
+        // bring the wait back
+        let mut recorded_rewind = false;
+        if self.awaiting_input && self.released_keys != 0 {
+            if self.rewind_depth > 0 {
+                self.record_rewind_delta();
+                recorded_rewind = true;
+            }
+            self.awaiting_input = false;
+            self.v[self.awaiter_index] = self.released_keys.trailing_zeros() as u8;
+            self.written |= 1 << self.awaiter_index;
+        }
+
+        // Breakpoints are checked before anything else, so hitting one
+        // leaves the machine untouched
+        if !self.awaiting_input && !self.awaiting_vblank {
+            let resuming = core::mem::take(&mut self.resuming_from_breakpoint);
+            if !resuming && self.breakpoints.contains(&self.pc) {
+                self.resuming_from_breakpoint = true;
+                return StepOutcome::BreakpointHit(self.pc)
+            }
+        }
+
+        if let Some(budget) = self.instruction_budget {
+            if !self.awaiting_input && !self.awaiting_vblank && self.budget_used >= budget {
+                return StepOutcome::BudgetExceeded
+            }
+        }
+
+        // fetch
+        if self.awaiting_input || self.awaiting_vblank {
+            return StepOutcome::Continue
+        }
+
+        // Stalled steps change nothing, recording them would only push real
+        // history out of the buffer
+        if self.rewind_depth > 0 && !recorded_rewind {
+            self.record_rewind_delta();
+        }
+
+        self.cycle_count += 1;
+        self.budget_used = self.budget_used.saturating_add(1);
+        let ir_pc = self.pc;
+        let (ir, instruction) = self.fetch();
+        self.advance_pc(2);
+        self.last_step_cost = self.timing.cost(instruction);
+        if self.profiling {
+            self.opcode_counts[instruction.profile_slot()] += 1;
+        }
+
+        let mut uninitialized_reads = 0;
+        if self.warn_uninitialized_reads {
+            let (reads, writes) = self.register_usage(instruction);
+            uninitialized_reads = reads & !self.written;
+            self.written |= writes;
+        }
+
+        if let Some(hook) = self.trace_hook.as_mut() {
+            hook(TraceEvent { pc: ir_pc, opcode: ir, v: self.v, i: self.i, uninitialized_reads });
+        }
+
+        // exec
+        match instruction {
+            Instruction::Cls => {
+                self.clear_display();
+            }
+            Instruction::Ret => {
+                self.pc = match self.stack.pop() {
+                    Some(addr) => addr,
+                    None => return StepOutcome::StackUnderflow,
+                };
+            }
+            Instruction::Exit => {
+                return StepOutcome::Exited
+            }
+            Instruction::Low => {
+                self.set_hires(false);
+            }
+            Instruction::High => {
+                self.set_hires(true);
+            }
+            Instruction::Sys(_) => {
+                if self.quirks.trap_sys {
+                    return StepOutcome::IllegalInstruction(ir)
+                }
+            }
+            Instruction::ScrollUp(n) => {
+                self.scroll_up(n as usize);
+            }
+            Instruction::Jp(addr) => {
+                self.pc = addr;
+            }
+            Instruction::Call(addr) => {
+                if self.stack.len() >= RIP8_STACK_MAX_SIZE {
+                    return StepOutcome::StackOverflow
+                }
+                self.stack.push(self.pc);
+                self.pc = addr;
+            }
+            Instruction::SeVxKk(x, k) => {
+                if self.v[x] == k {
+                    self.skip_next();
+                }
+            }
+            Instruction::SneVxKk(x, k) => {
+                if self.v[x] != k {
+                    self.skip_next();
+                }
+            }
+            Instruction::SeVxVy(x, y) => {
+                if self.v[x] == self.v[y] {
+                    self.skip_next();
+                }
+            }
+            Instruction::LdVxKk(x, k) => {
+                self.v[x] = k;
+            }
+            Instruction::AddVxKk(x, k) => {
+                self.v[x] = self.v[x].wrapping_add(k);
+            }
+            Instruction::LdVxVy(x, y) => {
+                self.v[x] = self.v[y];
+            }
+            Instruction::Or(x, y) => {
+                self.v[x] |= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xf] = 0;
+                }
+            }
+            Instruction::And(x, y) => {
+                self.v[x] &= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xf] = 0;
+                }
+            }
+            Instruction::Xor(x, y) => {
+                self.v[x] ^= self.v[y];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xf] = 0;
+                }
+            }
+            Instruction::AddVxVy(x, y) => {
+                let (v, o) = self.v[x].overflowing_add(self.v[y]);
+                self.v[x] = v;
+                self.v[0xf] = if o { 1 } else { 0 };
+            }
+            Instruction::Sub(x, y) => {
+                let (v, o) = self.v[x].overflowing_sub(self.v[y]);
+                self.v[x] = v;
+                self.v[0xf] = if o { 0 } else { 1 };
+            }
+            Instruction::Shr(x, y) => {
+                let o = if self.quirks.shift_uses_vy { y } else { x };
+                self.v[0xf] = self.v[o] & 0x1;
+                self.v[x] = self.v[o].overflowing_shr(1).0;
+            }
+            Instruction::Subn(x, y) => {
+                let (v, o) = self.v[y].overflowing_sub(self.v[x]);
+                self.v[x] = v;
+                self.v[0xf] = if o { 0 } else { 1 };
+            }
+            Instruction::Shl(x, y) => {
+                let o = if self.quirks.shift_uses_vy { y } else { x };
+                self.v[0xf] = (self.v[o] & 0x80) >> 7;
+                self.v[x] = self.v[o].overflowing_shl(1).0;
+            }
+            Instruction::SneVxVy(x, y) => {
+                if self.v[x] != self.v[y] {
+                    self.skip_next();
+                }
+            }
+            Instruction::LdI(addr) => {
+                self.i = addr;
+            }
+            Instruction::JpV0(addr) => {
+                // Under the quirk the register is the one named by the
+                // address' highest nibble
+                let o = if self.quirks.jump_with_vx { (addr >> 8) as usize } else { 0 };
+                self.pc = self.mem_index(addr.wrapping_add(self.v[o] as u16)) as u16;
+            }
+            Instruction::Rnd(x, k) => {
+                self.v[x] = (self.get_random.0)() & k;
+            }
+            Instruction::Drw(x, y, n) => {
+                // When drawing to both planes, the sprite data for the second
+                // plane comes right after the one for the first plane
+                // The starting coordinates always wrap, whether the rest of the
+                // sprite wraps or gets clipped depends on the quirk
+                let (width, height) = self.framebuffer_dimensions();
+                let start_x = self.v[x] as usize % width;
+                let start_y = self.v[y] as usize % height;
+                let mut collided_rows: u16 = 0; // one bit per sprite row
+                let mut clipped_rows = 0;
+                let mut sprite_address = self.i;
+                let (row_bytes, rows) = self.sprite_shape(n);
+                for plane in 0..RIP8_DISPLAY_PLANE_COUNT {
+                    if self.selected_planes & (1 << plane) == 0 {
+                        continue;
+                    }
+                    for idx in 0..rows {
+                        if self.quirks.clip_sprites && start_y + idx >= height {
+                            clipped_rows = (rows - idx) as u8;
+                            break;
+                        }
+                        for byte in 0..row_bytes {
+                            if self.quirks.clip_sprites && start_x + byte * 8 >= width {
+                                break;
+                            }
+                            let spot_byte = self.mem(sprite_address.wrapping_add((idx * row_bytes + byte) as u16));
+                            if self.set_spot_byte(plane, start_x + byte * 8, start_y + idx, spot_byte) {
+                                collided_rows |= 1 << idx;
+                            }
+                        }
+                    }
+                    sprite_address = sprite_address.wrapping_add((rows * row_bytes) as u16);
+                }
+                self.v[0xf] = if self.quirks.count_collided_rows && self.hires {
+                    collided_rows.count_ones() as u8 + clipped_rows
+                } else if collided_rows != 0 { 1 } else { 0 };
+                self.awaiting_vblank = self.quirks.display_wait;
+            }
+            Instruction::Skp(x) => {
+                if self.keyboard[self.v[x] as usize] {
+                    self.skip_next();
+                }
+            }
+            Instruction::Sknp(x) => {
+                if !self.keyboard[self.v[x] as usize] {
+                    self.skip_next();
+                }
+            }
+            Instruction::LdILong => {
+                // XO-CHIP long load, the address is stored in the following two
+                // bytes. Note that this can point I beyond the 4KB of memory
+                // CHIP-8 programs have available, in which case accesses wrap
+                // around, this only makes sense under the XO-CHIP memory model
+                let addr_hb = self.mem(self.pc);
+                self.advance_pc(1);
+                let addr_lb = self.mem(self.pc);
+                self.advance_pc(1);
+                self.i = u16::from_be_bytes([addr_hb, addr_lb]);
+            }
+            Instruction::Plane(planes) => {
+                self.selected_planes = planes;
+            }
+            Instruction::Audio => {
+                let mut pattern = [0x00; RIP8_AUDIO_PATTERN_SIZE];
+                for (idx, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.mem(self.i.wrapping_add(idx as u16));
+                }
+                self.audio_pattern = Some(pattern);
+            }
+            Instruction::LdVxDt(x) => {
+                self.v[x] = self.dt;
+            }
+            Instruction::LdVxK(x) => {
+                self.awaiting_input = true;
+                self.awaiter_index = x;
+                self.released_keys = 0;
+            }
+            Instruction::LdDtVx(x) => {
+                self.dt = self.v[x];
+            }
+            Instruction::LdStVx(x) => {
+                self.st = self.v[x];
+            }
+            Instruction::AddIVx(x) => {
+                self.i = self.i.wrapping_add(self.v[x] as u16);
+                if self.quirks.i_overflow_sets_vf {
+                    self.v[0xf] = if self.i > 0x0fff { 1 } else { 0 };
+                }
+            }
+            Instruction::LdFVx(x) => {
+                self.i = (self.v[x] & 0xf) as u16 * 5;
+            }
+            Instruction::LdBVx(x) => {
+                self.store(self.i, (self.v[x] / 100) % 10);
+                self.store(self.i.wrapping_add(1), (self.v[x] / 10) % 10);
+                self.store(self.i.wrapping_add(2), self.v[x] % 10);
+            }
+            Instruction::LdIVx(x) => {
+                for r in 0..(x+1) {
+                    self.store(self.i.wrapping_add(r as u16), self.v[r]);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            Instruction::LdVxI(x) => {
+                for r in 0..(x+1) {
+                    self.v[r] = self.mem(self.i.wrapping_add(r as u16));
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+            }
+            // 5xy2 and 5xy3 are XO-CHIP's, any other memory model treats them
+            // like the rest of the unknown 5xyn
+            Instruction::SaveVxVy(..) | Instruction::LoadVxVy(..) if self.memory_model != MemoryModel::XoChip => {
+                return StepOutcome::IllegalInstruction(ir)
+            }
+            Instruction::SaveVxVy(x, y) => {
+                for (offset, r) in register_range(x, y).enumerate() {
+                    self.store(self.i.wrapping_add(offset as u16), self.v[r]);
+                }
+            }
+            Instruction::LoadVxVy(x, y) => {
+                for (offset, r) in register_range(x, y).enumerate() {
+                    self.v[r] = self.mem(self.i.wrapping_add(offset as u16));
+                }
+            }
+            Instruction::Pitch(x) => {
+                self.audio_pitch = self.v[x];
+            }
+            Instruction::LdRVx(x) => {
+                for r in 0..(x.min(RIP8_RPL_FLAG_COUNT - 1) + 1) {
+                    self.rpl[r] = self.v[r];
+                }
+            }
+            Instruction::LdVxR(x) => {
+                for r in 0..(x.min(RIP8_RPL_FLAG_COUNT - 1) + 1) {
+                    self.v[r] = self.rpl[r];
+                }
+            }
+            Instruction::Illegal(ir) => {
+                // could not parse instruction, halt and catch fire
+                return StepOutcome::IllegalInstruction(ir)
+            }
+        }
+        if let Some(addr) = self.protection_fault.take() {
+            return StepOutcome::ProtectionFault(addr)
+        }
+        if let Some((addr, old, new)) = self.watchpoint_hit.take() {
+            return StepOutcome::WatchpointHit { addr, old, new }
+        }
+        if let Some(v_before) = v_before {
+            let changed = (0..0x10).find(|&r| self.watched_registers & (1 << r) != 0 && v_before[r] != self.v[r]);
+            if let Some(reg) = changed {
+                return StepOutcome::RegisterChanged { reg, old: v_before[reg], new: self.v[reg] }
+            }
+        }
+        if self.report_vblank_waits && self.awaiting_vblank {
+            return StepOutcome::VblankWait
+        }
+        StepOutcome::Continue
+    }
+
+    // Executes up to `n` instructions without letting any time go by, so
+    // timers stay put. Stops at the first outcome other than `Continue`
+    pub fn run_cycles(&mut self, n: usize) -> StepOutcome {
+        let mut outcome = StepOutcome::Continue;
+        for _ in 0..n {
+            outcome = self.step();
+            if outcome != StepOutcome::Continue {
+                break;
+            }
+        }
+        outcome
+    }
+
+    // Runs until the program stops (exit, illegal instruction, breakpoint...)
+    // letting time go by at the nominal frequency, gives up after
+    // `max_cycles` and returns `Continue` if the program never stopped
+    pub fn run_until_halt(&mut self, max_cycles: usize) -> StepOutcome {
+        let mut outcome = StepOutcome::Continue;
+        self.budget_used = 0;
+        for _ in 0..max_cycles {
+            self.advance_time(1.0 / self.freq as f64);
+            outcome = self.step();
+            if outcome != StepOutcome::Continue {
+                break;
+            }
+        }
+        outcome
+    }
+
+    // The display analog of stepping over: runs, letting time go by like
+    // `run_until_halt`, until a Dxyn has been executed so the returned
+    // outcome comes with the draw already applied. Also stops at any
+    // outcome other than `Continue`. Gives up with `NoDraw` after
+    // `max_cycles` without a draw
+    pub fn step_until_draw(&mut self, max_cycles: usize) -> StepOutcome {
+        self.budget_used = 0;
+        for _ in 0..max_cycles {
+            self.advance_time(1.0 / self.freq as f64);
+            let drawing = !self.awaiting_input && !self.awaiting_vblank
+                && matches!(decode(self.peek_opcode()), Instruction::Drw(..));
+            let cycles = self.cycle_count;
+            let outcome = self.step();
+            if outcome != StepOutcome::Continue || (drawing && self.cycle_count != cycles) {
+                return outcome
+            }
+        }
+        StepOutcome::NoDraw
+    }
+}
+
+// A decoded instruction, registers are given by index. Named after the
+// mnemonics `disassemble` prints
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,                    // 00E0
+    Ret,                    // 00EE
+    Exit,                   // 00FD
+    Low,                    // 00FE
+    High,                   // 00FF
+    Sys(u16),               // 0nnn, any other than 0000 and the ones here
+    ScrollUp(u8),           // 00Dn
+    Jp(u16),                // 1nnn
+    Call(u16),              // 2nnn
+    SeVxKk(usize, u8),      // 3xkk
+    SneVxKk(usize, u8),     // 4xkk
+    SeVxVy(usize, usize),   // 5xy0
+    SaveVxVy(usize, usize), // 5xy2
+    LoadVxVy(usize, usize), // 5xy3
+    LdVxKk(usize, u8),      // 6xkk
+    AddVxKk(usize, u8),     // 7xkk
+    LdVxVy(usize, usize),   // 8xy0
+    Or(usize, usize),       // 8xy1
+    And(usize, usize),      // 8xy2
+    Xor(usize, usize),      // 8xy3
+    AddVxVy(usize, usize),  // 8xy4
+    Sub(usize, usize),      // 8xy5
+    Shr(usize, usize),      // 8xy6
+    Subn(usize, usize),     // 8xy7
+    Shl(usize, usize),      // 8xyE
+    SneVxVy(usize, usize),  // 9xy0
+    LdI(u16),               // Annn
+    JpV0(u16),              // Bnnn
+    Rnd(usize, u8),         // Cxkk
+    Drw(usize, usize, u8),  // Dxyn
+    Skp(usize),             // Ex9E
+    Sknp(usize),            // ExA1
+    LdILong,                // F000 nnnn, the address is in the next two bytes
+    Plane(u8),              // Fx01, the planes are x's lower two bits
+    Audio,                  // F002
+    LdVxDt(usize),          // Fx07
+    LdVxK(usize),           // Fx0A
+    LdDtVx(usize),          // Fx15
+    LdStVx(usize),          // Fx18
+    AddIVx(usize),          // Fx1E
+    LdFVx(usize),           // Fx29
+    LdBVx(usize),           // Fx33
+    LdIVx(usize),           // Fx55
+    LdVxI(usize),           // Fx65
+    Pitch(usize),           // Fx3A
+    LdRVx(usize),           // Fx75
+    LdVxR(usize),           // Fx85
+    Illegal(u16),
+}
+
+// Names `opcode_histogram` reports each kind of instruction under, in the
+// order `profile_slot` numbers them
+const PROFILE_PATTERNS: [&str; PROFILE_SLOT_COUNT] = [
+    "00E0", "00EE", "00FD", "0nnn", "00Dn", "1nnn", "2nnn", "3xkk", "4xkk",
+    "5xy0", "6xkk", "7xkk", "8xy0", "8xy1", "8xy2", "8xy3", "8xy4", "8xy5",
+    "8xy6", "8xy7", "8xyE", "9xy0", "Annn", "Bnnn", "Cxkk", "Dxyn", "Ex9E",
+    "ExA1", "F000", "Fx01", "F002", "Fx07", "Fx0A", "Fx15", "Fx18", "Fx1E",
+    "Fx29", "Fx33", "Fx55", "Fx65", "Fx3A", "Fx75", "Fx85", "5xy2", "5xy3",
+    "00FE", "00FF", "illegal",
+];
+const PROFILE_SLOT_COUNT: usize = 48;
+
+impl Instruction {
+    fn profile_slot(self) -> usize {
+        match self {
+            Instruction::Cls => 0,
+            Instruction::Ret => 1,
+            Instruction::Exit => 2,
+            Instruction::Sys(_) => 3,
+            Instruction::ScrollUp(_) => 4,
+            Instruction::Jp(_) => 5,
+            Instruction::Call(_) => 6,
+            Instruction::SeVxKk(..) => 7,
+            Instruction::SneVxKk(..) => 8,
+            Instruction::SeVxVy(..) => 9,
+            Instruction::LdVxKk(..) => 10,
+            Instruction::AddVxKk(..) => 11,
+            Instruction::LdVxVy(..) => 12,
+            Instruction::Or(..) => 13,
+            Instruction::And(..) => 14,
+            Instruction::Xor(..) => 15,
+            Instruction::AddVxVy(..) => 16,
+            Instruction::Sub(..) => 17,
+            Instruction::Shr(..) => 18,
+            Instruction::Subn(..) => 19,
+            Instruction::Shl(..) => 20,
+            Instruction::SneVxVy(..) => 21,
+            Instruction::LdI(_) => 22,
+            Instruction::JpV0(_) => 23,
+            Instruction::Rnd(..) => 24,
+            Instruction::Drw(..) => 25,
+            Instruction::Skp(_) => 26,
+            Instruction::Sknp(_) => 27,
+            Instruction::LdILong => 28,
+            Instruction::Plane(_) => 29,
+            Instruction::Audio => 30,
+            Instruction::LdVxDt(_) => 31,
+            Instruction::LdVxK(_) => 32,
+            Instruction::LdDtVx(_) => 33,
+            Instruction::LdStVx(_) => 34,
+            Instruction::AddIVx(_) => 35,
+            Instruction::LdFVx(_) => 36,
+            Instruction::LdBVx(_) => 37,
+            Instruction::LdIVx(_) => 38,
+            Instruction::LdVxI(_) => 39,
+            Instruction::Pitch(_) => 40,
+            Instruction::LdRVx(_) => 41,
+            Instruction::LdVxR(_) => 42,
+            Instruction::SaveVxVy(..) => 43,
+            Instruction::LoadVxVy(..) => 44,
+            Instruction::Low => 45,
+            Instruction::High => 46,
+            Instruction::Illegal(_) => 47,
+        }
+    }
+}
+
+// Registers moved by XO-CHIP's 5xy2 and 5xy3, from Vx to Vy whichever way
+// they're ordered
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    let (low, high) = (x.min(y), x.max(y));
+    (low..=high).map(move |r| if x <= y { r } else { high + low - r })
+}
+
+pub fn decode(ir: u16) -> Instruction {
+    let x: usize = ((ir & 0x0f00) >> 8) as usize;
+    let y: usize = ((ir & 0x00f0) >> 4) as usize;
+    let k: u8 = (ir & 0x00ff) as u8;
+    let i: u16 = ir & 0x0fff;
+    let n: u8 = (ir & 0x000f) as u8; // this should really be a nibble,
+                                     // but there is no u4 in rust
+    match ir & 0xf000 {
+        0x0000 => match ir {
+            0x00e0 => Instruction::Cls,
+            0x00ee => Instruction::Ret,
+            0x00fd => Instruction::Exit,
+            0x00fe => Instruction::Low,
+            0x00ff => Instruction::High,
+            _ if ir & 0xfff0 == 0x00d0 => Instruction::ScrollUp(n),
+            // Blank memory and the S-CHIP instructions that aren't
+            // supported shouldn't pass for SYS
+            0x0000 | 0x00fb | 0x00fc => Instruction::Illegal(ir),
+            _ if ir & 0xfff0 == 0x00c0 => Instruction::Illegal(ir),
+            _ => Instruction::Sys(i),
+        },
+        0x1000 => Instruction::Jp(i),
+        0x2000 => Instruction::Call(i),
+        0x3000 => Instruction::SeVxKk(x, k),
+        0x4000 => Instruction::SneVxKk(x, k),
+        0x5000 => match n {
+            0x0 => Instruction::SeVxVy(x, y),
+            0x2 => Instruction::SaveVxVy(x, y),
+            0x3 => Instruction::LoadVxVy(x, y),
+            _ => Instruction::Illegal(ir),
+        },
+        0x6000 => Instruction::LdVxKk(x, k),
+        0x7000 => Instruction::AddVxKk(x, k),
+        0x8000 => match n {
+            0x0 => Instruction::LdVxVy(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddVxVy(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::Shr(x, y),
+            0x7 => Instruction::Subn(x, y),
+            0xe => Instruction::Shl(x, y),
+            _ => Instruction::Illegal(ir),
+        },
+        0x9000 if n == 0x0 => Instruction::SneVxVy(x, y),
+        0xa000 => Instruction::LdI(i),
+        0xb000 => Instruction::JpV0(i),
+        0xc000 => Instruction::Rnd(x, k),
+        0xd000 => Instruction::Drw(x, y, n),
+        0xe000 => match k {
+            0x9e => Instruction::Skp(x),
+            0xa1 => Instruction::Sknp(x),
+            _ => Instruction::Illegal(ir),
+        },
+        0xf000 => match k {
+            0x00 if x == 0x0 => Instruction::LdILong,
+            0x01 => Instruction::Plane(x as u8 & 0x3),
+            0x02 => Instruction::Audio,
+            0x07 => Instruction::LdVxDt(x),
+            0x0a => Instruction::LdVxK(x),
+            0x15 => Instruction::LdDtVx(x),
+            0x18 => Instruction::LdStVx(x),
+            0x1e => Instruction::AddIVx(x),
+            0x29 => Instruction::LdFVx(x),
+            0x33 => Instruction::LdBVx(x),
+            0x55 => Instruction::LdIVx(x),
+            0x65 => Instruction::LdVxI(x),
+            0x3a => Instruction::Pitch(x),
+            0x75 => Instruction::LdRVx(x),
+            0x85 => Instruction::LdVxR(x),
+            _ => Instruction::Illegal(ir),
+        },
+        _ => Instruction::Illegal(ir),
+    }
+}
+
+// Human readable form of an opcode, F000's trailing address shows as NNNN
+pub fn disassemble(ir: u16) -> String {
+    match decode(ir) {
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Low => "LOW".to_string(),
+        Instruction::High => "HIGH".to_string(),
+        Instruction::Sys(addr) => format!("SYS 0x{:03X}", addr),
+        Instruction::ScrollUp(n) => format!("SCU {}", n),
+        Instruction::Jp(addr) => format!("JP 0x{:03X}", addr),
+        Instruction::Call(addr) => format!("CALL 0x{:03X}", addr),
+        Instruction::SeVxKk(x, k) => format!("SE V{:X}, 0x{:02X}", x, k),
+        Instruction::SneVxKk(x, k) => format!("SNE V{:X}, 0x{:02X}", x, k),
+        Instruction::SeVxVy(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::LdVxKk(x, k) => format!("LD V{:X}, 0x{:02X}", x, k),
+        Instruction::AddVxKk(x, k) => format!("ADD V{:X}, 0x{:02X}", x, k),
+        Instruction::LdVxVy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddVxVy(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::Shr(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::Subn(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::Shl(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SneVxVy(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdI(addr) => format!("LD I, 0x{:03X}", addr),
+        Instruction::JpV0(addr) => format!("JP V0, 0x{:03X}", addr),
+        Instruction::Rnd(x, k) => format!("RND V{:X}, 0x{:02X}", x, k),
+        Instruction::Drw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::Skp(x) => format!("SKP V{:X}", x),
+        Instruction::Sknp(x) => format!("SKNP V{:X}", x),
+        Instruction::LdILong => "LD I, NNNN".to_string(),
+        Instruction::Plane(planes) => format!("PLANE {}", planes),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::LdVxDt(x) => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK(x) => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx(x) => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddIVx(x) => format!("ADD I, V{:X}", x),
+        Instruction::LdFVx(x) => format!("LD F, V{:X}", x),
+        Instruction::LdBVx(x) => format!("LD B, V{:X}", x),
+        Instruction::LdIVx(x) => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI(x) => format!("LD V{:X}, [I]", x),
+        Instruction::Pitch(x) => format!("PITCH V{:X}", x),
+        Instruction::SaveVxVy(x, y) => format!("SAVE V{:X}-V{:X}", x, y),
+        Instruction::LoadVxVy(x, y) => format!("LOAD V{:X}-V{:X}", x, y),
+        Instruction::LdRVx(x) => format!("LD R, V{:X}", x),
+        Instruction::LdVxR(x) => format!("LD V{:X}, R", x),
+        Instruction::Illegal(ir) => format!("ILLEGAL (0x{:04X})", ir),
+    }
+}
+
+// Disassembles `count` instructions starting at `start`, stopping early if
+// the end of memory is reached. A trailing lone byte is shown as data
+pub fn disassemble_range(memory: &[u8], start: u16, count: usize) -> Vec<(u16, String)> {
+    disassemble_range_with_symbols(memory, start, count, &BTreeMap::new())
+}
+
+// Same as `disassemble_range`, but labeled addresses are shown by name both
+// as operands and in front of the instruction they label
+pub fn disassemble_range_with_symbols(memory: &[u8], start: u16, count: usize, symbols: &BTreeMap<u16, String>) -> Vec<(u16, String)> {
+    let mut listing = Vec::with_capacity(count);
+    let mut addr = start as usize;
+    while listing.len() < count && addr < memory.len() {
+        let line_addr = addr as u16;
+        let text = if addr + 1 == memory.len() {
+            addr += 1;
+            format!("DB 0x{:02X}", memory[addr - 1])
+        } else {
+            let ir = u16::from_be_bytes([memory[addr], memory[addr + 1]]);
+            if ir == 0xf000 && addr + 3 < memory.len() {
+                let long_addr = u16::from_be_bytes([memory[addr + 2], memory[addr + 3]]);
+                addr += 4;
+                match symbols.get(&long_addr) {
+                    Some(name) => format!("LD I, {}", name),
+                    None => format!("LD I, 0x{:04X}", long_addr),
+                }
+            } else {
+                addr += 2;
+                disassemble_with_symbols(ir, symbols)
+            }
+        };
+        match symbols.get(&line_addr) {
+            Some(name) => listing.push((line_addr, format!("{}: {}", name, text))),
+            None => listing.push((line_addr, text)),
+        }
+    }
+    listing
+}
+
+// Same as `disassemble`, naming the target address when it's labeled
+pub fn disassemble_with_symbols(ir: u16, symbols: &BTreeMap<u16, String>) -> String {
+    let (mnemonic, addr) = match decode(ir) {
+        Instruction::Sys(addr) => ("SYS", addr),
+        Instruction::Jp(addr) => ("JP", addr),
+        Instruction::Call(addr) => ("CALL", addr),
+        Instruction::LdI(addr) => ("LD I,", addr),
+        Instruction::JpV0(addr) => ("JP V0,", addr),
+        _ => return disassemble(ir),
+    };
+    match symbols.get(&addr) {
+        Some(name) => format!("{} {}", mnemonic, name),
+        None => disassemble(ir),
+    }
+}
+
+// Parses a symbol file as emitted by assemblers, one `ADDR NAME` pair per
+// line with the address in hex (the 0x prefix is optional). Blank lines and
+// lines starting with '#' are skipped
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<u16, String>, Rip8Error> {
+    let mut symbols = BTreeMap::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (addr, name) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(addr), Some(name), None) => (addr, name),
+            _ => return Err(Rip8Error::SymbolsMalformed(idx + 1)),
+        };
+        let digits = addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")).unwrap_or(addr);
+        match u16::from_str_radix(digits, 16) {
+            Ok(addr) => symbols.insert(addr, name.to_string()),
+            Err(_) => return Err(Rip8Error::SymbolsMalformed(idx + 1)),
+        };
+    }
+    Ok(symbols)
+}
+
+// Result of `analyze_rom`. Since code and data are mixed together in CHIP-8
+// ROMs, sprites and other data get decoded as instructions too, so take the
+// findings as hints rather than facts
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomReport {
+    pub instruction_count: usize,
+    pub unknown_opcodes: Vec<(u16, u16)>, // address and opcode
+    pub uses_s_chip: bool,
+    pub uses_xo_chip: bool,
+    pub highest_register: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OpcodeFamily {
+    Chip8,
+    SChip,
+    XoChip,
+    Unknown,
+}
+
+// Which flavor of CHIP-8 an opcode belongs to and the highest register it
+// names as an operand
+fn classify_opcode(ir: u16) -> (OpcodeFamily, Option<usize>) {
+    match decode(ir) {
+        Instruction::Cls | Instruction::Ret | Instruction::Jp(_) | Instruction::Call(_) |
+        Instruction::LdI(_) => (OpcodeFamily::Chip8, None),
+        Instruction::JpV0(_) => (OpcodeFamily::Chip8, Some(0)),
+        Instruction::SeVxKk(x, _) | Instruction::SneVxKk(x, _) | Instruction::LdVxKk(x, _) |
+        Instruction::AddVxKk(x, _) | Instruction::Rnd(x, _) | Instruction::Skp(x) |
+        Instruction::Sknp(x) | Instruction::LdVxDt(x) | Instruction::LdVxK(x) |
+        Instruction::LdDtVx(x) | Instruction::LdStVx(x) | Instruction::AddIVx(x) |
+        Instruction::LdFVx(x) | Instruction::LdBVx(x) | Instruction::LdIVx(x) |
+        Instruction::LdVxI(x) => (OpcodeFamily::Chip8, Some(x)),
+        Instruction::SeVxVy(x, y) | Instruction::SneVxVy(x, y) | Instruction::Drw(x, y, _) |
+        Instruction::LdVxVy(x, y) | Instruction::Or(x, y) | Instruction::And(x, y) |
+        Instruction::Xor(x, y) | Instruction::AddVxVy(x, y) | Instruction::Sub(x, y) |
+        Instruction::Shr(x, y) | Instruction::Subn(x, y) |
+        Instruction::Shl(x, y) => (OpcodeFamily::Chip8, Some(x.max(y))),
+        Instruction::Exit | Instruction::Low | Instruction::High => (OpcodeFamily::SChip, None),
+        Instruction::LdRVx(x) | Instruction::LdVxR(x) => (OpcodeFamily::SChip, Some(x)),
+        Instruction::ScrollUp(_) | Instruction::LdILong | Instruction::Plane(_) |
+        Instruction::Audio => (OpcodeFamily::XoChip, None),
+        Instruction::Pitch(x) => (OpcodeFamily::XoChip, Some(x)),
+        Instruction::SaveVxVy(x, y) | Instruction::LoadVxVy(x, y) => (OpcodeFamily::XoChip, Some(x.max(y))),
+        // SYS runs nothing here, which the ROM probably didn't expect
+        Instruction::Sys(_) | Instruction::Illegal(_) => (OpcodeFamily::Unknown, None),
+    }
+}
+
+// Decodes the whole ROM, two bytes at a time, as if it was all code (see
+// `RomReport` for why that's only a best effort)
+pub fn analyze_rom(rom: &[u8], load_addr: u16) -> RomReport {
+    let mut report = RomReport::default();
+    let mut offset = 0;
+    while offset + 1 < rom.len() {
+        let ir = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        let addr = load_addr.wrapping_add(offset as u16);
+        let (family, register) = classify_opcode(ir);
+        match family {
+            OpcodeFamily::Chip8 => {},
+            OpcodeFamily::SChip => report.uses_s_chip = true,
+            OpcodeFamily::XoChip => report.uses_xo_chip = true,
+            OpcodeFamily::Unknown => report.unknown_opcodes.push((addr, ir)),
+        }
+        if register.is_some() && register > report.highest_register {
+            report.highest_register = register;
+        }
+        report.instruction_count += 1;
+        // F000's address operand is not an instruction
+        offset += if ir == 0xf000 { 4 } else { 2 };
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rip8::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    const ALWAYS_42: fn() -> u8 = || -> u8 { 0x42 };
+    const ALWAYS_ZERO: fn() -> u8 = || -> u8 { 0x00 };
+    const DEFAULT_FREQUENCY: u32 = 480;
+
+   fn rip8_with_rom(rom: &Vec<u8>) -> Rip8 {
+        Rip8::from_rom(rom, DEFAULT_FREQUENCY, ALWAYS_ZERO)
+    }
+
+    // One instruction's worth of time at the default frequency, then the
+    // instruction itself
+    fn step_cycle(rip8: &mut Rip8) -> StepOutcome {
+        rip8.tick_timers(1.0 / DEFAULT_FREQUENCY as f64);
+        rip8.step()
+    }
+
+    fn run(rip8: &mut Rip8) {
+        while step_cycle(rip8) == StepOutcome::Continue { }
+    }
+
+    fn run_rom_with_random(rom: &Vec<u8>, random: fn() -> u8) -> Rip8 {
+        let mut rip8 = Rip8::from_rom(rom, 480, random);
+        run(&mut rip8);
+        rip8
+    }
+
+    fn run_rom(rom: &Vec<u8>) -> Rip8 {
+        run_rom_with_random(rom, ALWAYS_ZERO)
+    }
+
+    fn run_rom_with_quirks(rom: &Vec<u8>, quirks: Quirks) -> Rip8 {
+        let mut rip8 = rip8_with_rom(rom);
+        rip8.set_quirks(quirks);
+        run(&mut rip8);
+        rip8
+    }
+
+    fn append_trailing_data_to_rom(code: &mut Vec<u8>, mut trailing_data: Vec<u8>) -> u16 {
+        let sprite_length = trailing_data.len();
+        let sprite_address = RIP8_ROM_START + (code.len() & 0xffff) as u16 + 2;
+
+        code.append(&mut trailing_data);
+
+        code.insert(0, 0xa0 | (sprite_address >> 8) as u8);
+        code.insert(1, (sprite_address & 0xff) as u8);
+
+        RIP8_ROM_START + (code.len() - sprite_length) as u16
+    }
+
+    #[test]
+    fn test_jp_zero() {
+        let rom = vec![0x10, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, 0x0002)
+    }
+
+    #[test]
+    fn test_jp_chained() {
+        let rom = vec![0x12, 0x02, 0x10, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, 0x0002)
+    }
+
+    #[test]
+    fn test_call_zero() {
+        let rom = vec![0x20, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, 0x0002);
+        assert_eq!(rip8.stack, vec![0x0202]);
+    }
+
+    #[test]
+    fn test_ld_const() {
+        let rom = vec![0x60, 0x12, 0x6c, 0x54];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x12);
+        assert_eq!(rip8.v[0xc], 0x54);
+    }
+
+    #[test]
+    fn test_se_const_taken() {
+        let rom = vec![0x60, 0x12, 0x30, 0x12];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x12);
+    }
+
+    #[test]
+    fn test_se_const_not_taken() {
+        let rom = vec![0x60, 0x12, 0x30, 0x13];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x12);
+    }
+
+    #[test]
+    fn test_sne_const_taken() {
+        let rom = vec![0x60, 0x12, 0x40, 0x13];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x12);
+    }
+
+    #[test]
+    fn test_sne_const_not_taken() {
+        let rom = vec![0x60, 0x12, 0x40, 0x12];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x12);
+    }
+
+    #[test]
+    fn test_se_reg_taken() {
+        let rom = vec![0x60, 0x12, 0x61, 0x12, 0x50, 0x10];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
+        assert_eq!(rip8.v[0x0], 0x12);
+        assert_eq!(rip8.v[0x1], 0x12);
+    }
+
+    #[test]
+    fn test_se_reg_not_taken() {
+        let rom = vec![0x60, 0x12, 0x61, 0x13, 0x50, 0x10];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x12);
+        assert_eq!(rip8.v[0x1], 0x13);
+    }
+
+    #[test]
+    fn test_add_const() {
+        let rom = vec![0x60, 0x12, 0x70, 0x21];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x33);
+    }
+
+    #[test]
+    fn test_add_const_overflow() {
+        let rom = vec![0x60, 0xff, 0x70, 0x01];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x00);
+    }
+
+    #[test]
+    fn test_ld_reg() {
+        let rom = vec![0x60, 0x12, 0x83, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0x0], 0x12);
+        assert_eq!(rip8.v[0x3], 0x12);
+    }
+
+    #[test]
+    fn test_or() {
+        let rom = vec![0x60, 0x07, 0x61, 0xe0, 0x80, 0x11];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0xe7);
+        assert_eq!(rip8.v[0x1], 0xe0);
+    }
+
+    #[test]
+    fn test_and() {
+        let rom = vec![0x68, 0x07, 0x6a, 0xec, 0x88, 0xa2];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x8], 0x04);
+        assert_eq!(rip8.v[0xa], 0xec);
+    }
+
+    #[test]
+    fn test_xor() {
+        let rom = vec![0x6b, 0x1f, 0x6a, 0xf8, 0x8b, 0xa3];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0xb], 0xe7);
+        assert_eq!(rip8.v[0xa], 0xf8);
+    }
+
+    #[test]
+    fn test_logic_resets_vf() {
+        let quirks = Quirks { logic_resets_vf: true, ..Quirks::default() };
+        for op in [0x01, 0x02, 0x03] {
+            let rom = vec![0x6f, 0x01, 0x68, 0x07, 0x6a, 0xec, 0x88, 0xa0 | op];
+
+            let rip8 = run_rom_with_quirks(&rom, quirks);
+
+            assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
+            assert_eq!(rip8.v[0xf], 0);
+        }
+    }
+
+    #[test]
+    fn test_logic_keeps_vf() {
+        for op in [0x01, 0x02, 0x03] {
+            let rom = vec![0x6f, 0x01, 0x68, 0x07, 0x6a, 0xec, 0x88, 0xa0 | op];
+
+            let rip8 = run_rom(&rom);
+
+            assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
+            assert_eq!(rip8.v[0xf], 1);
+        }
+    }
+
+    #[test]
+    fn test_add_flags_without_carry() {
+        let rom = vec![0x64, 0x78, 0x6e, 0x32, 0x84, 0xe4];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x4], 0xaa);
+        assert_eq!(rip8.v[0xe], 0x32);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_add_flags_with_carry() {
+        let rom = vec![0x64, 0xff, 0x6e, 0x01, 0x84, 0xe4];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x4], 0x00);
+        assert_eq!(rip8.v[0xe], 0x01);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_sub_flags_without_borrow() {
+        let rom = vec![0x64, 0x01, 0x63, 0x01, 0x84, 0x35];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x4], 0x00);
+        assert_eq!(rip8.v[0x3], 0x01);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_sub_flags_with_borrow() {
+        let rom = vec![0x64, 0x00, 0x63, 0x01, 0x84, 0x35];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x4], 0xff);
+        assert_eq!(rip8.v[0x3], 0x01);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_shr_lsb_zero() {
+        let rom = vec![0x60, 0x00, 0x62, 0x02, 0x80, 0x26];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x01);
+        assert_eq!(rip8.v[0x2], 0x02);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_shr_lsb_set() {
+        let rom = vec![0x60, 0x00, 0x62, 0x81, 0x80, 0x26];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x40);
+        assert_eq!(rip8.v[0x2], 0x81);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_shr_overflow() {
+        let rom = vec![0x60, 0x00, 0x62, 0x01, 0x80, 0x26];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x00);
+        assert_eq!(rip8.v[0x2], 0x01);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_shr_in_place() {
+        let rom = vec![0x60, 0x05, 0x62, 0x80, 0x80, 0x26];
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::schip());
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x02);
+        assert_eq!(rip8.v[0x2], 0x80);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_subn_without_borrow() {
+        let rom = vec![0x60, 0x00, 0x61, 0x01, 0x80, 0x17];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x01);
+        assert_eq!(rip8.v[0x1], 0x01);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_subn_with_borrow() {
+        let rom = vec![0x60, 0x02, 0x61, 0x01, 0x80, 0x17];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0xff);
+        assert_eq!(rip8.v[0x1], 0x01);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_shl_msb_zero() {
+        let rom = vec![0x60, 0x00, 0x61, 0x08, 0x80, 0x1e];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x10);
+        assert_eq!(rip8.v[0x1], 0x08);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_shl_msb_set() {
+        let rom = vec![0x60, 0x00, 0x61, 0x88, 0x80, 0x1e];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x10);
+        assert_eq!(rip8.v[0x1], 0x88);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_shl_overflow() {
+        let rom = vec![0x60, 0x00, 0x61, 0x80, 0x80, 0x1e];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x00);
+        assert_eq!(rip8.v[0x1], 0x80);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_shl_in_place() {
+        let rom = vec![0x60, 0x41, 0x61, 0x01, 0x80, 0x1e];
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::schip());
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x82);
+        assert_eq!(rip8.v[0x1], 0x01);
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_sne_reg_taken() {
+        let rom = vec![0x60, 0x44, 0x61, 0x88, 0x90, 0x10];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
+        assert_eq!(rip8.v[0x0], 0x44);
+        assert_eq!(rip8.v[0x1], 0x88);
+    }
+
+    #[test]
+    fn test_sne_reg_not_taken() {
+        let rom = vec![0x60, 0x44, 0x61, 0x44, 0x90, 0x10];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
+        assert_eq!(rip8.v[0x0], 0x44);
+        assert_eq!(rip8.v[0x1], 0x44);
+    }
+
+    #[test]
+    fn test_ld_addr() {
+        let rom = vec![0xa1, 0x23];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x4);
+        assert_eq!(rip8.i, 0x0123);
+    }
+
+    #[test]
+    fn test_ld_long_addr() {
+        let rom = vec![0xf0, 0x00, 0x0a, 0xbc, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.i, 0x0abc);
+    }
+
+    #[test]
+    fn test_jp_offset() {
+        let rom = vec![0x60, 0x12, 0xb3, 0x21];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, 0x335);
+        assert_eq!(rip8.v[0], 0x12);
+    }
+
+    #[test]
+    fn test_jp_offset_quirk() {
+        let rom = vec![
+            0x60, 0x10, // v0 = 0x10
+            0x62, 0x20, // v2 = 0x20
+            0xb2, 0x20, // jp v0 (or v2), 0x220
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.run_cycles(3);
+        assert_eq!(rip8.pc, 0x230);
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { jump_with_vx: true, ..Quirks::default() });
+        rip8.run_cycles(3);
+        assert_eq!(rip8.pc, 0x240);
+    }
+
+    #[test]
+    fn test_rnd_fixed() {
+        let rom = vec![0xc0, 0xff, 0xc1, 0x61];
+
+        let rip8 = run_rom_with_random(&rom, ALWAYS_42);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
+        assert_eq!(rip8.v[0], 0x42);
+        assert_eq!(rip8.v[1], 0x40);
+    }
+
+    #[test]
+    fn test_draw_stripes() {
+        let mut rom: Vec<u8> = vec![0x60, 0x00, 0xd0, 0x08, 0x00, 0x00];
+        let sprite: Vec<u8> = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.i, stop_address);
+        assert_eq!(rip8.pc, stop_address);
+        for y in 0..32 {
+            for x in 0..64 {
+                if x < 8 && y < 8 && x % 2 == y % 2 {
+                    assert!(rip8.get_display_spot(x, y));
+                } else {
+                    assert!(!rip8.get_display_spot(x, y));
+                }
+            }
+        }
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_draw_unset_spot() {
+        let mut rom: Vec<u8> = vec![0x60, 0x00, 0xd0, 0x08, 0xd0, 0x08, 0x00, 0x00];
+        let sprite: Vec<u8> = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.i, stop_address);
+        assert_eq!(rip8.pc, stop_address);
+        for y in 0..32 {
+            for x in 0..64 {
+                assert!(!rip8.get_display_spot(x, y));
+            }
+        }
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_draw_stripes_offset() {
+        let mut rom = vec![0x61, 0x01, 0xd1, 0x18, 0x00, 0x00];
+        let sprite = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.i, stop_address);
+        assert_eq!(rip8.pc, stop_address);
+        for y in 0..32 {
+            for x in 0..64 {
+                if x > 0 && x < 9 && y > 0 && y < 9 && x % 2 == y % 2 {
+                    assert!(rip8.get_display_spot(x, y));
+                } else {
+                    assert!(!rip8.get_display_spot(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_wrapped() {
+        let mut rom = vec![0x61, 0x39, 0x62, 0x19, 0xd1, 0x28, 0x00, 0x00];
+        let sprite = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.i, stop_address);
+        assert_eq!(rip8.pc, stop_address);
+        for y in 0..32 {
+            for x in 0..64 {
+                if (x == 0 && y ==  0) ||
+                    (y > 24 && x > 56) ||
+                    (y > 24 && x == 0) ||
+                    (y ==0 && x > 56) {
+                    assert!(rip8.get_display_spot(x, y));
+                } else {
+                    assert!(!rip8.get_display_spot(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_display_dirty() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0xf0, 0x29, // i = digits[v0]
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0x70, 0x01, // v0 += 1
+            0x00, 0xe0, // cls
+            0x00, 0x00
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert!(rip8.take_display_dirty());
+        assert!(!rip8.take_display_dirty());
+        step_cycle(&mut rip8);
+        step_cycle(&mut rip8);
+        assert!(!rip8.take_display_dirty());
+        step_cycle(&mut rip8);
+        assert!(rip8.take_display_dirty());
+        step_cycle(&mut rip8);
+        assert!(!rip8.take_display_dirty());
+        step_cycle(&mut rip8);
+        assert!(rip8.take_display_dirty());
+        assert!(!rip8.take_display_dirty());
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        let mut rom = vec![0x60, 0x0c, 0x61, 0x01, 0xd0, 0x12, 0x00, 0x00];
+        let sprite = vec![0xf0, 0x81];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        let (width, height) = rip8.framebuffer_dimensions();
+        let framebuffer = rip8.framebuffer();
+        assert_eq!(framebuffer.len(), RIP8_DISPLAY_SIZE);
+        assert_eq!(framebuffer[9], 0x0f);
+        assert_eq!(framebuffer[17], 0x08);
+        assert_eq!(framebuffer[18], 0x10);
+        for y in 0..height {
+            for x in 0..width {
+                let bit = y * width + x;
+                let spot = framebuffer[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                assert_eq!(spot, rip8.get_display_spot(x, y));
+            }
+        }
+        assert!(rip8.plane_framebuffer(1).iter().all(|byte| *byte == 0x00));
+    }
+
+    #[test]
+    fn test_lit_pixels() {
+        let mut rom = vec![0x60, 0x03, 0x61, 0x02, 0xd0, 0x12, 0x00, 0x00];
+        let sprite = vec![0x81, 0x40];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.lit_pixels(), vec![(3, 2), (10, 2), (4, 3)]);
+    }
+
+    #[test]
+    fn test_draw_second_plane() {
+        let mut rom = vec![0x60, 0x00, 0xf2, 0x01, 0xd0, 0x01, 0x00, 0x00];
+        let sprite = vec![0xf0];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.selected_planes, 0x2);
+        for x in 0..8 {
+            assert_eq!(rip8.get_display_pixel(x, 0), if x < 4 { 2 } else { 0 });
+        }
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_draw_both_planes() {
+        let mut rom = vec![0x60, 0x00, 0xf3, 0x01, 0xd0, 0x01, 0x00, 0x00];
+        let sprite = vec![0xf0, 0x3c];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        let expected = [1, 1, 3, 3, 2, 2, 0, 0];
+        for (x, pixel) in expected.iter().enumerate() {
+            assert_eq!(rip8.get_display_pixel(x, 0), *pixel);
+        }
+        assert!(rip8.get_display_spot(4, 0));
+        assert_eq!(rip8.v[0xf], 0);
+    }
+
+    #[test]
+    fn test_draw_plane_collision() {
+        let mut rom = vec![
+            0x60, 0x00,
+            0xf2, 0x01, // select plane 2
+            0xd0, 0x01, // draw on plane 2
+            0xf3, 0x01, // select both planes
+            0xd0, 0x01, // draw on both planes, collides on plane 2
+            0x00, 0x00
+        ];
+        let sprite = vec![0x80, 0x80];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.get_display_pixel(0, 0), 1);
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_draw_clipped() {
+        let mut rom = vec![0x61, 0x39, 0x62, 0x19, 0xd1, 0x28, 0x00, 0x00];
+        let sprite = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::cosmac_vip());
+
+        assert_eq!(rip8.pc, stop_address);
+        for y in 0..32 {
+            for x in 0..64 {
+                if y > 24 && x > 56 {
+                    assert!(rip8.get_display_spot(x, y));
+                } else {
+                    assert!(!rip8.get_display_spot(x, y));
+                }
+            }
+        }
+
+        // Straddle the right edge over pixels already lit on the left edge,
+        // only the pixels actually drawn may cause a collision
+        let mut rom = vec![
+            0x61, 0x00, // v1 = 0
+            0x62, 0x3c, // v2 = 60
+            0xd1, 0x11, // draw i..i[1] at (v1, v1)
+            0xd2, 0x11, // draw i..i[1] at (v2, v1)
+            0x00, 0x00,
+        ];
+        append_trailing_data_to_rom(&mut rom, vec![0xff]);
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::cosmac_vip());
+        assert_eq!(rip8.v[0xf], 0);
+        for x in 0..64 {
+            assert_eq!(rip8.get_display_spot(x, 0), !(8..60).contains(&x));
+        }
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::default());
+        assert_eq!(rip8.v[0xf], 1);
+        for x in 0..64 {
+            assert_eq!(rip8.get_display_spot(x, 0), (4..8).contains(&x) || x >= 60);
+        }
+    }
+
+    #[test]
+    fn test_draw_bottom_row_out_of_range_y() {
+        // v2 = 0xff lands on row 31, and the second sprite row would be 0x100
+        // if the coordinate wasn't wrapped first
+        let mut rom = vec![0x61, 0x00, 0x62, 0xff, 0xd1, 0x22, 0x00, 0x00];
+        let sprite = vec![0x80, 0x80];
+        append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+        assert!(rip8.get_display_spot(0, 31));
+        assert!(rip8.get_display_spot(0, 0));
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::cosmac_vip());
+        assert!(rip8.get_display_spot(0, 31));
+        assert!(!rip8.get_display_spot(0, 0));
+    }
+
+    #[test]
+    fn test_display_wait() {
+        let rom = vec![
+            0x61, 0x00, // v1 = 0
+            0xd0, 0x01, // draw i..i[1] at (v0, v0)
+            0x71, 0x01, // v1 += 1
+            0x12, 0x02, // jp draw
+        ];
+        let cycles_per_frame = DEFAULT_FREQUENCY / 60;
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        for frame in 0..10 {
+            for _ in 0..cycles_per_frame {
+                assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+            }
+            assert_eq!(rip8.v[1], frame + 1);
+        }
+
+        let mut rip8 = rip8_with_rom(&rom);
+        for _ in 0..cycles_per_frame * 10 {
+            assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        }
+        assert_eq!(rip8.v[1], 26);
+    }
+
+    #[test]
+    fn test_skp_taken() {
+        let rom = vec![0x63, 0x01, 0xe3, 0x9e, 0x00, 0x00];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_keydown(1, true);
+        run(&mut rip8);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16 + 2);
+    }
+
+    #[test]
+    fn test_skp_not_taken() {
+        let rom = vec![0x63, 0x01, 0xe3, 0x9e, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+    }
+
+    #[test]
+    fn test_sknp_taken() {
+        let rom = vec![0x62, 0x05, 0xe2, 0xa1, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16 + 2);
+    }
+
+    #[test]
+    fn test_sknp_not_taken() {
+        let rom = vec![0x62, 0x00, 0xe2, 0xa1, 0x00, 0x00];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_keydown(0, true);
+        run(&mut rip8);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+    }
+
+    #[test]
+    fn test_ld_reg_dt() {
+        let rom = vec![0x60, 0xff, 0xf0, 0x07, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.v[0], rip8.dt);
+    }
+
+    #[test]
+    fn test_ld_input() {
+        let rom = vec![0xf0, 0x0a, 0xff, 0x0a, 0x00, 0x00];
+
+        let mut rip8 = rip8_with_rom(&rom);
+
+        // no matter how much we run, it should stop until it receives input
+        for _ in 0..50 {
+            step_cycle(&mut rip8);
+        }
+        rip8.set_keydown(0xf, true);
+        step_cycle(&mut rip8);
+        rip8.set_keydown(0xf, false);
+        for _ in 0..50 {
+            step_cycle(&mut rip8);
+        }
+        rip8.set_keydown(0x0, true);
+        step_cycle(&mut rip8);
+        rip8.set_keydown(0x0, false);
+        // finish running
+        run(&mut rip8);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.v[0x0], 0xf);
+        assert_eq!(rip8.v[0xf], 0x0);
+    }
+
+    #[test]
+    fn test_ld_dt_reg() {
+        let rom = vec![0x61, 0x42, 0xf1, 0x15, 0x00, 0x00];
+
+        let rip8 = run_rom_with_random(&rom, ALWAYS_42);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.dt, rip8.v[0x1]);
+        assert_eq!(rip8.v[0x1], 0x42);
+    }
+
+    #[test]
+    fn test_ld_st_reg() {
+        let rom = vec![0x61, 0x42, 0xf1, 0x18, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.st, rip8.v[0x1]);
+        assert_eq!(rip8.v[0x1], 0x42);
+    }
+
+    #[test]
+    fn test_ld_audio_pattern() {
+        let mut rom = vec![0xf0, 0x02, 0x00, 0x00];
+        let pattern = vec![
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+            0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff
+        ];
+        let stop_address = append_trailing_data_to_rom(&mut rom, pattern.clone());
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.get_audio_pattern(), None);
+        run(&mut rip8);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.get_audio_pattern().unwrap().to_vec(), pattern);
+    }
+
+    #[test]
+    fn test_ld_audio_pitch() {
+        let rom = vec![0x63, 0x70, 0xf3, 0x3a, 0x00, 0x00];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.get_audio_playback_rate(), 4000.0);
+        run(&mut rip8);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.get_audio_pitch(), 0x70);
+        assert_eq!(rip8.get_audio_playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_add_i_reg() {
+        let rom = vec![0x61, 0x32, 0xa1, 0x23, 0xf1, 0x1e, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.v[0x1], 0x32);
+        assert_eq!(rip8.i, 0x155);
+    }
+
+    #[test]
+    fn test_ld_sprite_0() {
+        let rom = vec![0x60, 0x00, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_1() {
+        let rom = vec![0x60, 0x01, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0x20);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x60);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x20);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x20);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x70);
+    }
+
+    #[test]
+    fn test_ld_sprite_2() {
+        let rom = vec![0x60, 0x02, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_3() {
+        let rom = vec![0x60, 0x03, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_4() {
+        let rom = vec![0x60, 0x04, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x10);
+    }
+
+    #[test]
+    fn test_ld_sprite_5() {
+        let rom = vec![0x60, 0x05, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_6() {
+        let rom = vec![0x60, 0x06, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_7() {
+        let rom = vec![0x60, 0x07, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x20);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x40);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x40);
+    }
+
+    #[test]
+    fn test_ld_sprite_8() {
+        let rom = vec![0x60, 0x08, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_9() {
+        let rom = vec![0x60, 0x09, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_a() {
+        let rom = vec![0x60, 0x0a, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x90);
+    }
+
+    #[test]
+    fn test_ld_sprite_b() {
+        let rom = vec![0x60, 0x0b, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xe0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xe0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xe0);
+    }
+
+    #[test]
+    fn test_ld_sprite_c() {
+        let rom = vec![0x60, 0x0c, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_d() {
+        let rom = vec![0x60, 0x0d, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xe0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xe0);
+    }
+
+    #[test]
+    fn test_ld_sprite_e() {
+        let rom = vec![0x60, 0x0e, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    }
+
+    #[test]
+    fn test_ld_sprite_f() {
+        let rom = vec![0x60, 0x0f, 0xf0, 0x29, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
+        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
+        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x80);
+    }
+
+    #[test]
+    fn test_ld_bcd() {
+        let rom = vec![
+            0x60, 0xc6, // v0 = 0xc6
+            0x61, 0x4c, // v1 = 0x4c
+            0x62, 0xfe, // v2 = 0xfe
+            0x63, 0x03, // v3 = 0x03
+            0x64, 0x03, // v4 = 0x03
+            0xa6, 0x00, // i = 0x300
+            0xf0, 0x33, // *i = bcd(v0) = 198
+            0xf4, 0x1e, // i += 3
+            0xf1, 0x33, // *i = bcd(v1) = 76
+            0xf4, 0x1e, // i += 3
+            0xf2, 0x33, // *i = bcd(v2) = 254
+            0xf4, 0x1e, // i += 3
+            0xf3, 0x33, // *i = bcd(v3) = 3
+            0xf4, 0x1e, // i += 3
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.i, 0x60c);
+        assert_eq!(rip8.memory[rip8.i as usize - 01], 0x03);
+        assert_eq!(rip8.memory[rip8.i as usize - 02], 0x00);
+        assert_eq!(rip8.memory[rip8.i as usize - 03], 0x00);
+
+        assert_eq!(rip8.memory[rip8.i as usize - 04], 0x04);
+        assert_eq!(rip8.memory[rip8.i as usize - 05], 0x05);
+        assert_eq!(rip8.memory[rip8.i as usize - 06], 0x02);
+
+        assert_eq!(rip8.memory[rip8.i as usize - 07], 0x06);
+        assert_eq!(rip8.memory[rip8.i as usize - 08], 0x07);
+        assert_eq!(rip8.memory[rip8.i as usize - 09], 0x00);
+
+        assert_eq!(rip8.memory[rip8.i as usize - 10], 0x08);
+        assert_eq!(rip8.memory[rip8.i as usize - 11], 0x09);
+        assert_eq!(rip8.memory[rip8.i as usize - 12], 0x01);
+    }
+
+    #[test]
+    fn test_store_registers() {
+        let rom = vec![
+            0x60, 0xff,
+            0x61, 0x88,
+            0x62, 0x44,
+            0x63, 0x00,
+            0xa6, 0x00,
+            0xf3, 0x55,
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.i, 0x600 + 3 + 1);
+        assert_eq!(rip8.memory[rip8.i as usize - 01], 0x00);
+        assert_eq!(rip8.memory[rip8.i as usize - 02], 0x44);
+        assert_eq!(rip8.memory[rip8.i as usize - 03], 0x88);
+        assert_eq!(rip8.memory[rip8.i as usize - 04], 0xff);
+    }
+
+    #[test]
+    fn test_load_registers() {
+        let mut rom = vec![
+            0x64, 0xff,
+            0xf3, 0x65,
+            0x00, 0x00
+        ];
+        let trailer = vec![0x42, 0x43, 0x44, 0x45];
+        let stop_address = append_trailing_data_to_rom(&mut rom, trailer);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.i, stop_address + 4);
+        assert_eq!(rip8.v[0], 0x42);
+        assert_eq!(rip8.v[1], 0x43);
+        assert_eq!(rip8.v[2], 0x44);
+        assert_eq!(rip8.v[3], 0x45);
+    }
+
+    #[test]
+    fn test_store_registers_without_increment() {
+        let rom = vec![
+            0x60, 0x12,
+            0x61, 0x34,
+            0xa6, 0x00,
+            0xf1, 0x55,
+            0xf1, 0x65,
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom_with_quirks(&rom, Quirks::schip());
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.i, 0x600);
+        assert_eq!(rip8.memory[0x600], 0x12);
+        assert_eq!(rip8.memory[0x601], 0x34);
+    }
+
+    #[test]
+    fn test_store_load_rpl_flags() {
+        let rom = vec![
+            0x60, 0x11,
+            0x61, 0x22,
+            0x62, 0x33,
+            0x63, 0x44,
+            0xf3, 0x75, // rpl[0..=3] = v0..v3
+            0x60, 0x00,
+            0x61, 0x00,
+            0x62, 0x00,
+            0x63, 0x00,
+            0xf3, 0x85, // v0..v3 = rpl[0..=3]
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.rpl[0..4], [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(rip8.v[0..4], [0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn test_store_load_rpl_flags_clamped() {
+        let rom = vec![
+            0x67, 0x77,
+            0x6f, 0x42,
+            0xff, 0x75, // rpl[0..=7] = v0..v7
+            0x67, 0x00,
+            0xff, 0x85, // v0..v7 = rpl[0..=7]
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.rpl[7], 0x77);
+        assert_eq!(rip8.v[7], 0x77);
+        assert_eq!(rip8.v[0xf], 0x42);
+    }
+
+    #[test]
+    fn test_cls() {
+        let rom = vec![0x00, 0xe0, 0x00, 0x00];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        for x in 0..64 {
+            for y in 0..32 {
+                assert!(!rip8.get_display_spot(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_then_cls() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0xf0, 0x29, // i = digits[v0]
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0x00, 0xe0, // cls
+            0x00, 0x00
+        ];
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        for x in 0..64 {
+            for y in 0..32 {
+                assert!(!rip8.get_display_spot(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cls_clears_whole_buffer() {
+        let mut rom = vec![
+            0x60, 0x00, // v0 = 0
+            0x61, 0x00, // v1 = 0
+            0xd0, 0x18, // draw i..i[8] at (v0, v1)
+            0x70, 0x08, // v0 += 8
+            0x30, 0x40, // skip if v0 == 64
+            0x12, 0x06, // jp draw
+            0x60, 0x00, // v0 = 0
+            0x71, 0x08, // v1 += 8
+            0x31, 0x20, // skip if v1 == 32
+            0x12, 0x06, // jp draw
+            0x00, 0xe0, // cls
+            0x00, 0x00
+        ];
+        let sprite = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert!(rip8.display.iter().all(|byte| *byte == 0x00));
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut rom = vec![
+            0x60, 0x08, // v0 = 8
+            0x61, 0x06, // v1 = 6
+            0xd0, 0x13, // draw i..i[3] at (v0, v1)
+            0x61, 0x1e, // v1 = 30
+            0xd0, 0x12, // draw i..i[2] at (v0, v1)
+            0x00, 0xd4, // scroll up 4 rows
+            0x00, 0x00
+        ];
+        let sprite = vec![0xff, 0x81, 0xff];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        let mut expected = Vec::new();
+        for x in 8..16 {
+            expected.push((x, 2));
+        }
+        expected.push((8, 3));
+        expected.push((15, 3));
+        for x in 8..16 {
+            expected.push((x, 4));
+        }
+        for x in 8..16 {
+            expected.push((x, 26));
+        }
+        expected.push((8, 27));
+        expected.push((15, 27));
+        assert_eq!(rip8.lit_pixels(), expected);
+    }
+
+    #[test]
+    fn test_scroll_up_selected_plane() {
+        let mut rom = vec![
+            0x60, 0x00, // v0 = 0
+            0x61, 0x04, // v1 = 4
+            0xf3, 0x01, // select both planes
+            0xd0, 0x11, // draw i..i[2] at (v0, v1)
+            0xf2, 0x01, // select plane 2
+            0x00, 0xd2, // scroll up 2 rows
+            0x00, 0x00
+        ];
+        let sprite = vec![0x80, 0x80];
+        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+
+        let rip8 = run_rom(&rom);
+
+        assert_eq!(rip8.pc, stop_address);
+        assert_eq!(rip8.get_display_pixel(0, 4), 1);
+        assert_eq!(rip8.get_display_pixel(0, 2), 2);
+        assert_eq!(rip8.lit_pixels(), vec![(0, 2), (0, 4)]);
+    }
+
+    #[test]
+    fn test_warm_reset() {
+        let rom = vec![
+            0x60, 0x07, // v0 = 7
+            0xf0, 0x29, // i = digits[v0]
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0xf0, 0x15, // dt = v0
+            0xf0, 0x18, // st = v0
+            0x00, 0x00
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_keydown(3, true);
+        run(&mut rip8);
+        rip8.warm_reset();
+
+        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        assert_eq!(rip8.v[0], 0x07);
+        assert_eq!(rip8.i, 0x07 * 5);
+        assert!(rip8.lit_pixels().is_empty());
+        assert_eq!(rip8.dt, 0);
+        assert_eq!(rip8.st, 0);
+        assert!(!rip8.keyboard[3]);
     }
 
     #[test]
-    fn test_ld_reg() {
-        let rom = vec![0x60, 0x12, 0x83, 0x00];
+    fn test_exit() {
+        let rom = vec![0x00, 0xfd, 0x00, 0x00];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0x0], 0x12);
-        assert_eq!(rip8.v[0x3], 0x12);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Exited);
+        assert_eq!(rip8.pc, RIP8_ROM_START + 2);
     }
 
     #[test]
-    fn test_or() {
-        let rom = vec![0x60, 0x07, 0x61, 0xe0, 0x80, 0x11];
+    fn test_illegal_instruction() {
+        let rom = vec![0x8f, 0x0a];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0xe7);
-        assert_eq!(rip8.v[0x1], 0xe0);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::IllegalInstruction(0x8f0a));
     }
 
     #[test]
-    fn test_and() {
-        let rom = vec![0x68, 0x07, 0x6a, 0xec, 0x88, 0xa2];
+    fn test_stack_underflow() {
+        let rom = vec![0x00, 0xee];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x8], 0x04);
-        assert_eq!(rip8.v[0xa], 0xec);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::StackUnderflow);
     }
 
     #[test]
-    fn test_xor() {
-        let rom = vec![0x6b, 0x1f, 0x6a, 0xf8, 0x8b, 0xa3];
+    fn test_stack_overflow() {
+        let rom = vec![0x22, 0x00];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        for _ in 0..RIP8_STACK_MAX_SIZE {
+            assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0xb], 0xe7);
-        assert_eq!(rip8.v[0xa], 0xf8);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::StackOverflow);
     }
 
     #[test]
-    fn test_add_flags_without_carry() {
-        let rom = vec![0x64, 0x78, 0x6e, 0x32, 0x84, 0xe4];
-
-        let rip8 = run_rom(&rom);
+    fn test_reset() {
+        let rom = vec![
+            0x60, 0x07, // v0 = 7
+            0xf0, 0x29, // i = digits[v0]
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0xf0, 0x18, // st = v0
+            0xa4, 0x00, // i = 0x400
+            0xf0, 0x33, // *i = bcd(v0)
+            0x22, 0x10, // call 0x210
+            0x00, 0x00,
+            0xf0, 0x0a, // v0 = key
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x4], 0xaa);
-        assert_eq!(rip8.v[0xe], 0x32);
-        assert_eq!(rip8.v[0xf], 0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_keydown(4, true);
+        for _ in 0..16 {
+            step_cycle(&mut rip8);
+        }
+        assert!(rip8.awaiting_input);
+        rip8.reset();
+
+        let fresh = rip8_with_rom(&rom);
+        assert_eq!(rip8.pc, fresh.pc);
+        assert_eq!(rip8.memory, fresh.memory);
+        assert_eq!(rip8.stack, fresh.stack);
+        assert_eq!(rip8.v, fresh.v);
+        assert_eq!(rip8.i, fresh.i);
+        assert_eq!(rip8.display, fresh.display);
+        assert_eq!(rip8.keyboard, fresh.keyboard);
+        assert_eq!(rip8.dt, fresh.dt);
+        assert_eq!(rip8.st, fresh.st);
+        assert_eq!(rip8.awaiting_input, fresh.awaiting_input);
+        assert_eq!(rip8.elapsed, fresh.elapsed);
     }
 
     #[test]
-    fn test_add_flags_with_carry() {
-        let rom = vec![0x64, 0xff, 0x6e, 0x01, 0x84, 0xe4];
+    fn test_call_ret() {
+        let rom = vec![0x22, 0x04, 0x00, 0x00, 0x00, 0xee];
 
         let rip8 = run_rom(&rom);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x4], 0x00);
-        assert_eq!(rip8.v[0xe], 0x01);
-        assert_eq!(rip8.v[0xf], 1);
+        assert_eq!(rip8.pc, RIP8_ROM_START + 4);
+        assert!(rip8.stack.is_empty());
     }
 
     #[test]
-    fn test_sub_flags_without_borrow() {
-        let rom = vec![0x64, 0x01, 0x63, 0x01, 0x84, 0x35];
+    fn test_delta_rewind() {
+        let rom = vec![
+            0x60, 0x07, // v0 = 7
+            0xf0, 0x29, // i = digits[v0]
+            0x22, 0x08, // call 0x208
+            0x00, 0x00,
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0xa4, 0x00, // i = 0x400
+            0xf0, 0x33, // *i = bcd(v0)
+            0xf0, 0x55, // *i = v0
+            0x00, 0xe0, // cls
+            0x00, 0xee, // ret
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.enable_delta_rewind(16);
+        step_cycle(&mut rip8);
+        step_cycle(&mut rip8);
+        let pc = rip8.pc;
+        let v = rip8.v;
+        let i = rip8.i;
+        let stack = rip8.stack.clone();
+        let memory = rip8.memory.clone();
+        let display = rip8.display.clone();
+        for _ in 0..7 {
+            step_cycle(&mut rip8);
+        }
+        assert_eq!(rip8.pc, RIP8_ROM_START + 6);
+        assert_eq!(rip8.memory[0x400], 0x07);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x4], 0x00);
-        assert_eq!(rip8.v[0x3], 0x01);
-        assert_eq!(rip8.v[0xf], 1);
+        for _ in 0..7 {
+            assert!(rip8.step_back());
+        }
+
+        assert_eq!(rip8.pc, pc);
+        assert_eq!(rip8.v, v);
+        assert_eq!(rip8.i, i);
+        assert_eq!(rip8.stack, stack);
+        assert_eq!(rip8.memory, memory);
+        assert_eq!(rip8.display, display);
+        assert!(rip8.step_back());
+        assert!(rip8.step_back());
+        assert!(!rip8.step_back());
+        assert_eq!(rip8.pc, RIP8_ROM_START);
     }
 
     #[test]
-    fn test_sub_flags_with_borrow() {
-        let rom = vec![0x64, 0x00, 0x63, 0x01, 0x84, 0x35];
+    fn test_delta_rewind_depth() {
+        let rom = vec![0x70, 0x01, 0x12, 0x00];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.enable_delta_rewind(2);
+        for _ in 0..6 {
+            step_cycle(&mut rip8);
+        }
+        assert_eq!(rip8.v[0], 0x02);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x4], 0xff);
-        assert_eq!(rip8.v[0x3], 0x01);
-        assert_eq!(rip8.v[0xf], 0);
+        assert!(rip8.step_back());
+        assert!(rip8.step_back());
+        assert!(!rip8.step_back());
+        assert_eq!(rip8.v[0], 0x01);
+        assert_eq!(rip8.pc, RIP8_ROM_START);
     }
 
     #[test]
-    fn test_shr_lsb_zero() {
-        let rom = vec![0x60, 0x00, 0x62, 0x02, 0x80, 0x26];
+    fn test_trace_hook() {
+        let rom = vec![0x60, 0x12, 0xa3, 0x45, 0x00, 0x00];
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let hook_events = events.clone();
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_trace_hook(Box::new(move |event| hook_events.borrow_mut().push(event)));
+        run(&mut rip8);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x01);
-        assert_eq!(rip8.v[0x2], 0x02);
-        assert_eq!(rip8.v[0xf], 0);
+        let mut v = [0xff; 16];
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], TraceEvent { pc: 0x200, opcode: 0x6012, v, i: 0xff, uninitialized_reads: 0 });
+        v[0] = 0x12;
+        assert_eq!(events[1], TraceEvent { pc: 0x202, opcode: 0xa345, v, i: 0xff, uninitialized_reads: 0 });
+        assert_eq!(events[2], TraceEvent { pc: 0x204, opcode: 0x0000, v, i: 0x345, uninitialized_reads: 0 });
     }
 
     #[test]
-    fn test_shr_lsb_set() {
-        let rom = vec![0x60, 0x00, 0x62, 0x81, 0x80, 0x26];
+    fn test_breakpoint() {
+        let rom = vec![0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x00];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.add_breakpoint(RIP8_ROM_START + 4);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::BreakpointHit(RIP8_ROM_START + 4));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x40);
-        assert_eq!(rip8.v[0x2], 0x81);
-        assert_eq!(rip8.v[0xf], 1);
+        assert_eq!(rip8.pc, RIP8_ROM_START + 4);
+        assert_eq!(rip8.v[0..3], [0x01, 0x02, 0xff]);
+
+        // Resuming executes the instruction under the breakpoint
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(rip8.v[2], 0x03);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::BreakpointHit(RIP8_ROM_START + 4));
+
+        rip8.remove_breakpoint(RIP8_ROM_START + 4);
+        assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        for _ in 0..8 {
+            assert_eq!(step_cycle(&mut rip8), StepOutcome::Continue);
+        }
     }
 
     #[test]
-    fn test_shr_overflow() {
-        let rom = vec![0x60, 0x00, 0x62, 0x01, 0x80, 0x26];
+    fn test_register_accessors() {
+        let rom = vec![0x61, 0x42, 0xf1, 0x15, 0xf1, 0x18, 0x00, 0x00];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        run(&mut rip8);
+        assert_eq!(rip8.get_register(1), 0x42);
+        assert_eq!(rip8.get_register(0x10), 0);
+        assert_eq!(rip8.get_delay_timer(), 0x42);
+        assert_eq!(rip8.get_sound_timer(), 0x42);
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + rom.len() as u16);
+
+        rip8.set_register(0xf, 0x12);
+        rip8.set_register(0x10, 0x34);
+        rip8.set_i(0x345);
+        rip8.set_pc(RIP8_ROM_START + 1);
+        assert_eq!(rip8.get_register(0xf), 0x12);
+        assert_eq!(rip8.v, [0xff, 0x42, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x12]);
+        assert_eq!(rip8.get_i(), 0x345);
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 1);
+
+        // Executes 0x42f1, which skips since V2 != 0xf1
+        assert_eq!(rip8.step(), StepOutcome::Continue);
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 5);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x00);
-        assert_eq!(rip8.v[0x2], 0x01);
-        assert_eq!(rip8.v[0xf], 1);
+    #[test]
+    fn test_memory_accessors() {
+        let rom = vec![0x12, 0x34];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.read_memory(RIP8_ROM_START), 0x12);
+        assert_eq!(rip8.read_memory(0x0000), 0xf0);
+
+        rip8.write_memory(0x0fff, 0xab);
+        rip8.write_memory(0x1000, 0xcd);
+        assert_eq!(rip8.read_memory(0x0fff), 0xab);
+        assert_eq!(rip8.read_memory(0x0000), 0xcd);
+        assert_eq!(rip8.read_memory(0x1fff), 0xab);
+
+        assert_eq!(rip8.memory_slice(RIP8_ROM_START, 2), [0x12, 0x34]);
+        assert_eq!(rip8.memory_slice(0x0ffe, 4), [0xff, 0xab]);
+        assert!(rip8.memory_slice(0x1000, 4).is_empty());
     }
 
     #[test]
-    fn test_subn_without_borrow() {
-        let rom = vec![0x60, 0x00, 0x61, 0x01, 0x80, 0x17];
+    fn test_disassemble() {
+        let cases = [
+            (0x00e0, "CLS"),
+            (0x00ee, "RET"),
+            (0x00fd, "EXIT"),
+            (0x00fe, "LOW"),
+            (0x00ff, "HIGH"),
+            (0x00d4, "SCU 4"),
+            (0x1234, "JP 0x234"),
+            (0x2abc, "CALL 0xABC"),
+            (0x3a12, "SE VA, 0x12"),
+            (0x4b34, "SNE VB, 0x34"),
+            (0x5120, "SE V1, V2"),
+            (0x6012, "LD V0, 0x12"),
+            (0x7f01, "ADD VF, 0x01"),
+            (0x8120, "LD V1, V2"),
+            (0x8121, "OR V1, V2"),
+            (0x8122, "AND V1, V2"),
+            (0x8123, "XOR V1, V2"),
+            (0x8124, "ADD V1, V2"),
+            (0x8125, "SUB V1, V2"),
+            (0x8126, "SHR V1, V2"),
+            (0x8127, "SUBN V1, V2"),
+            (0x812e, "SHL V1, V2"),
+            (0x9120, "SNE V1, V2"),
+            (0xa123, "LD I, 0x123"),
+            (0xb220, "JP V0, 0x220"),
+            (0xc3ff, "RND V3, 0xFF"),
+            (0xd128, "DRW V1, V2, 8"),
+            (0xe49e, "SKP V4"),
+            (0xe5a1, "SKNP V5"),
+            (0xf000, "LD I, NNNN"),
+            (0xf201, "PLANE 2"),
+            (0xf002, "AUDIO"),
+            (0xf607, "LD V6, DT"),
+            (0xf70a, "LD V7, K"),
+            (0xf815, "LD DT, V8"),
+            (0xf918, "LD ST, V9"),
+            (0xfa1e, "ADD I, VA"),
+            (0xfb29, "LD F, VB"),
+            (0xfc33, "LD B, VC"),
+            (0xfd3a, "PITCH VD"),
+            (0xfe55, "LD [I], VE"),
+            (0xff65, "LD VF, [I]"),
+            (0xf375, "LD R, V3"),
+            (0xf385, "LD V3, R"),
+            (0x8f0a, "ILLEGAL (0x8F0A)"),
+            (0x5121, "ILLEGAL (0x5121)"),
+            (0xe0ff, "ILLEGAL (0xE0FF)"),
+        ];
 
-        let rip8 = run_rom(&rom);
+        for (ir, mnemonic) in cases {
+            assert_eq!(disassemble(ir), mnemonic);
+        }
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x01);
-        assert_eq!(rip8.v[0x1], 0x01);
-        assert_eq!(rip8.v[0xf], 1);
+    #[test]
+    fn test_disassemble_range() {
+        let memory = [0x60, 0x12, 0xf0, 0x00, 0x12, 0x34, 0xd0, 0x15, 0x00];
+
+        assert_eq!(disassemble_range(&memory, 0, 2), vec![
+            (0, "LD V0, 0x12".to_string()),
+            (2, "LD I, 0x1234".to_string()),
+        ]);
+        assert_eq!(disassemble_range(&memory, 6, 10), vec![
+            (6, "DRW V0, V1, 5".to_string()),
+            (8, "DB 0x00".to_string()),
+        ]);
     }
 
     #[test]
-    fn test_subn_with_borrow() {
-        let rom = vec![0x60, 0x02, 0x61, 0x01, 0x80, 0x17];
+    fn test_dt_counts_down_at_60hz() {
+        let rom = vec![0x60, 0xff, 0xf0, 0x15, 0x12, 0x04];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.step();
+        rip8.step();
+        assert_eq!(rip8.dt, 0xff);
+        rip8.tick_timers(1.0);
+        assert_eq!(rip8.dt, 0xc3);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0xff);
-        assert_eq!(rip8.v[0x1], 0x01);
-        assert_eq!(rip8.v[0xf], 0);
+        // Executing instructions takes no time by itself
+        rip8.run_cycles(1000);
+        assert_eq!(rip8.dt, 0xc3);
+        rip8.tick_timers(0.5 / 60.0);
+        assert_eq!(rip8.dt, 0xc3);
+        rip8.tick_timers(0.5 / 60.0);
+        assert_eq!(rip8.dt, 0xc2);
     }
 
     #[test]
-    fn test_shl_msb_zero() {
-        let rom = vec![0x60, 0x00, 0x61, 0x08, 0x80, 0x1e];
+    fn test_try_from_image_wrong_size() {
+        let image = vec![0x00; RIP8_MEMORY_SIZE - 1];
 
-        let rip8 = run_rom(&rom);
+        let result = Rip8::try_from_image_at_start(&image, DEFAULT_FREQUENCY, RIP8_ROM_START, ALWAYS_ZERO);
+        assert_eq!(result.err(), Some(Rip8Error::ImageWrongSize(RIP8_MEMORY_SIZE - 1)));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x10);
-        assert_eq!(rip8.v[0x1], 0x08);
-        assert_eq!(rip8.v[0xf], 0);
+        let image = vec![0x00; RIP8_MEMORY_SIZE];
+        assert!(Rip8::try_from_image_at_start(&image, DEFAULT_FREQUENCY, RIP8_ROM_START, ALWAYS_ZERO).is_ok());
     }
 
     #[test]
-    fn test_shl_msb_set() {
-        let rom = vec![0x60, 0x00, 0x61, 0x88, 0x80, 0x1e];
+    fn test_try_from_rom_too_large() {
+        let rom = vec![0x00; RIP8_MEMORY_SIZE - RIP8_ROM_START as usize + 1];
 
-        let rip8 = run_rom(&rom);
+        let result = Rip8::try_from_rom_at_address(&rom, DEFAULT_FREQUENCY, RIP8_ROM_START, ALWAYS_ZERO);
+        assert_eq!(result.err(), Some(Rip8Error::RomTooLarge(rom.len())));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x10);
-        assert_eq!(rip8.v[0x1], 0x88);
-        assert_eq!(rip8.v[0xf], 1);
+        let rom = vec![0x00; RIP8_MEMORY_SIZE - RIP8_ROM_START as usize];
+        assert!(Rip8::try_from_rom_at_address(&rom, DEFAULT_FREQUENCY, RIP8_ROM_START, ALWAYS_ZERO).is_ok());
     }
 
     #[test]
-    fn test_shl_overflow() {
-        let rom = vec![0x60, 0x00, 0x61, 0x80, 0x80, 0x1e];
-
-        let rip8 = run_rom(&rom);
+    fn test_try_from_rom_load_address_too_low() {
+        let rom = vec![0x12, 0x00];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x00);
-        assert_eq!(rip8.v[0x1], 0x80);
-        assert_eq!(rip8.v[0xf], 1);
+        let result = Rip8::try_from_rom_at_address(&rom, DEFAULT_FREQUENCY, RIP8_ROM_START - 1, ALWAYS_ZERO);
+        assert_eq!(result.err(), Some(Rip8Error::LoadAddressTooLow(RIP8_ROM_START - 1)));
     }
 
     #[test]
-    fn test_sne_reg_taken() {
-        let rom = vec![0x60, 0x44, 0x61, 0x88, 0x90, 0x10];
+    fn test_serialize_round_trip() {
+        let rom = vec![
+            0x60, 0x20, // v0 = 0x20
+            0xf0, 0x15, // dt = v0
+            0x22, 0x08, // call draw
+            0x12, 0x04, // jp call
+            0xc1, 0xff, // draw: v1 = rand
+            0xf1, 0x29, // i = font(v1)
+            0xd1, 0x15, // draw i..i[5] at (v1, v1)
+            0x00, 0xee, // ret
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, ALWAYS_42);
+        for _ in 0..25 {
+            step_cycle(&mut rip8);
+        }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
-        assert_eq!(rip8.v[0x0], 0x44);
-        assert_eq!(rip8.v[0x1], 0x88);
+        let mut restored = Rip8::deserialize(&rip8.serialize(), ALWAYS_42).unwrap();
+        assert_eq!(restored.serialize(), rip8.serialize());
+        for _ in 0..100 {
+            assert_eq!(step_cycle(&mut restored), step_cycle(&mut rip8));
+        }
+        assert_eq!(restored.serialize(), rip8.serialize());
+        assert_eq!(restored.pc, rip8.pc);
+        assert_eq!(restored.dt, rip8.dt);
+        assert_eq!(restored.display, rip8.display);
     }
 
     #[test]
-    fn test_sne_reg_not_taken() {
-        let rom = vec![0x60, 0x44, 0x61, 0x44, 0x90, 0x10];
+    fn test_deserialize_errors() {
+        let state = rip8_with_rom(&vec![0x12, 0x00]).serialize();
 
-        let rip8 = run_rom(&rom);
+        assert_eq!(Rip8::deserialize(b"RIP", ALWAYS_ZERO).err(), Some(Rip8Error::SaveStateBadMagic));
+        assert_eq!(Rip8::deserialize(&state[1..], ALWAYS_ZERO).err(), Some(Rip8Error::SaveStateBadMagic));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x8);
-        assert_eq!(rip8.v[0x0], 0x44);
-        assert_eq!(rip8.v[0x1], 0x44);
+        let mut future = state.clone();
+        future[4] = 0xff;
+        assert_eq!(Rip8::deserialize(&future, ALWAYS_ZERO).err(), Some(Rip8Error::SaveStateVersion(0xff)));
+
+        assert_eq!(Rip8::deserialize(&state[..state.len() - 1], ALWAYS_ZERO).err(), Some(Rip8Error::SaveStateTruncated));
     }
 
     #[test]
-    fn test_ld_addr() {
-        let rom = vec![0xa1, 0x23];
+    #[cfg(feature = "serde")]
+    fn test_serde_bincode_round_trip() {
+        let rom = vec![
+            0xc1, 0xff, // v1 = rand
+            0xf1, 0x29, // i = font(v1)
+            0xd1, 0x15, // draw i..i[5] at (v1, v1)
+            0x71, 0x01, // v1 += 1
+            0x12, 0x02, // jp font
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, ALWAYS_42);
+        for _ in 0..7 {
+            step_cycle(&mut rip8);
+        }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x4);
-        assert_eq!(rip8.i, 0x0123);
+        let bytes = bincode::serialize(&rip8).unwrap();
+        let mut restored = bincode::deserialize::<Rip8>(&bytes).unwrap().with_random(ALWAYS_42);
+        for _ in 0..50 {
+            assert_eq!(step_cycle(&mut restored), step_cycle(&mut rip8));
+        }
+        assert_eq!(restored.pc, rip8.pc);
+        assert_eq!(restored.v, rip8.v);
+        assert_eq!(restored.i, rip8.i);
+        assert_eq!(restored.memory, rip8.memory);
+        assert_eq!(restored.display, rip8.display);
+        assert_eq!(restored.get_quirks(), rip8.get_quirks());
     }
 
     #[test]
-    fn test_jp_offset() {
-        let rom = vec![0x60, 0x12, 0xb3, 0x21];
-
-        let rip8 = run_rom(&rom);
+    fn test_run_cycles() {
+        let rom = vec![
+            0x60, 0x01, // v0 = 1
+            0x70, 0x01, // v0 += 1
+            0x70, 0x01, // v0 += 1
+            0x00, 0xfd, // exit
+        ];
 
-        assert_eq!(rip8.pc, 0x335);
-        assert_eq!(rip8.v[0], 0x12);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_cycles(2), StepOutcome::Continue);
+        assert_eq!(rip8.get_register(0), 2);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Exited);
+        assert_eq!(rip8.get_register(0), 3);
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 8);
     }
 
     #[test]
-    fn test_rnd_fixed() {
-        let rom = vec![0xc0, 0xff, 0xc1, 0x61];
+    fn test_run_until_halt() {
+        // Waits for the delay timer before exiting, so time must go by
+        let rom = vec![
+            0x60, 0x02, // v0 = 2
+            0xf0, 0x15, // dt = v0
+            0xf1, 0x07, // v1 = dt
+            0x31, 0x00, // skip if v1 == 0
+            0x12, 0x04, // jp check
+            0x00, 0xfd, // exit
+        ];
 
-        let rip8 = run_rom_with_random(&rom, ALWAYS_42);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_cycles(1000), StepOutcome::Continue);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 0x6);
-        assert_eq!(rip8.v[0], 0x42);
-        assert_eq!(rip8.v[1], 0x40);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_until_halt(1000), StepOutcome::Exited);
+
+        let mut rip8 = rip8_with_rom(&vec![0x12, 0x00]);
+        assert_eq!(rip8.run_until_halt(1000), StepOutcome::Continue);
     }
 
     #[test]
-    fn test_draw_stripes() {
-        let mut rom: Vec<u8> = vec![0x60, 0x00, 0xd0, 0x08, 0x00, 0x00];
-        let sprite: Vec<u8> = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
-        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+    fn test_xo_chip_memory_model() {
+        // Long load an address way past 4KB and read from it
+        let mut rom = vec![0x00; 0x2000];
+        rom[..8].copy_from_slice(&[
+            0xf0, 0x00, 0x20, 0x00, // i = 0x2000
+            0xf0, 0x65,             // v0 = i[0]
+            0x00, 0xfd,             // exit
+        ]);
+        rom[0x2000 - RIP8_ROM_START as usize] = 0x42;
+
+        let result = Rip8::try_from_rom_at_address(&rom, DEFAULT_FREQUENCY, RIP8_ROM_START, ALWAYS_ZERO);
+        assert_eq!(result.err(), Some(Rip8Error::RomTooLarge(rom.len())));
+
+        let mut rip8 = Rip8::try_from_rom_with_memory_model(&rom, DEFAULT_FREQUENCY, RIP8_ROM_START, MemoryModel::XoChip, ALWAYS_ZERO).unwrap();
+        assert_eq!(rip8.get_memory_model(), MemoryModel::XoChip);
+        assert_eq!(rip8.memory.len(), RIP8_XO_CHIP_MEMORY_SIZE);
+        assert_eq!(rip8.run_until_halt(100), StepOutcome::Exited);
+        assert_eq!(rip8.get_register(0), 0x42);
+
+        let image = rip8.memory.clone();
+        let rip8 = Rip8::from_image(&image, DEFAULT_FREQUENCY, ALWAYS_ZERO);
+        assert_eq!(rip8.get_memory_model(), MemoryModel::XoChip);
+    }
 
-        let rip8 = run_rom(&rom);
+    #[test]
+    fn test_chip8_memory_wraps_around() {
+        let rom = vec![
+            0xf0, 0x00, 0x10, 0x00, // i = 0x1000
+            0x60, 0x42,             // v0 = 0x42
+            0xf0, 0x55,             // i[0] = v0
+            0x00, 0xfd,             // exit
+        ];
 
-        assert_eq!(rip8.i, stop_address);
-        assert_eq!(rip8.pc, stop_address);
-        for y in 0..32 {
-            for x in 0..64 {
-                if x < 8 && y < 8 && x % 2 == y % 2 {
-                    assert!(rip8.get_display_spot(x, y));
-                } else {
-                    assert!(!rip8.get_display_spot(x, y));
-                }
-            }
-        }
-        assert_eq!(rip8.v[0xf], 0);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_until_halt(100), StepOutcome::Exited);
+        assert_eq!(rip8.read_memory(0x000), 0x42);
     }
 
     #[test]
-    fn test_draw_unset_spot() {
-        let mut rom: Vec<u8> = vec![0x60, 0x00, 0xd0, 0x08, 0xd0, 0x08, 0x00, 0x00];
-        let sprite: Vec<u8> = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
-        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+    fn test_to_rgba() {
+        let rom = vec![
+            0x60, 0x01, // v0 = 1
+            0xf0, 0x29, // i = font(v0)
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0x00, 0xfd, // exit
+        ];
+        let fg = [0x00, 0xff, 0x00, 0xff];
+        let bg = [0x10, 0x20, 0x30, 0x40];
+        let palette = [bg, fg, [0xff, 0x00, 0x00, 0xff], [0x00, 0x00, 0xff, 0xff]];
 
         let rip8 = run_rom(&rom);
+        let (width, height, rgba) = rip8.to_rgba(&palette, 1);
+        assert_eq!((width, height), (RIP8_DISPLAY_WIDTH as u32, RIP8_DISPLAY_HEIGHT as u32));
+        assert_eq!(rgba.len(), RIP8_DISPLAY_WIDTH * RIP8_DISPLAY_HEIGHT * 4);
+        let pixel = |x: usize, y: usize| &rgba[(y * RIP8_DISPLAY_WIDTH + x) * 4..][..4];
+        // The "1" glyph's top row is 0x20, and its bottom row is 0x70
+        assert_eq!(pixel(3, 1), fg);
+        assert_eq!(pixel(1, 1), bg);
+        assert_eq!(pixel(2, 5), fg);
+        assert_eq!(pixel(0, 0), bg);
+
+        let (width, height, rgba) = rip8.to_rgba(&palette, 3);
+        assert_eq!((width, height), (RIP8_DISPLAY_WIDTH as u32 * 3, RIP8_DISPLAY_HEIGHT as u32 * 3));
+        assert_eq!(&rgba[(5 * width as usize + 11) * 4..][..4], fg);
+
+        // Spots lit on the second plane only take the third color
+        let mut rom = vec![
+            0x60, 0x00, // v0 = 0
+            0xf2, 0x01, // select plane 2
+            0xd0, 0x01, // draw i..i[1] at (v0, v0)
+            0x00, 0x00,
+        ];
+        append_trailing_data_to_rom(&mut rom, vec![0x80]);
+        let rip8 = run_rom(&rom);
+        let (_, _, rgba) = rip8.to_rgba(&palette, 1);
+        assert_eq!(&rgba[..4], palette[2]);
+        assert_eq!(&rgba[4..8], bg);
+    }
 
-        assert_eq!(rip8.i, stop_address);
-        assert_eq!(rip8.pc, stop_address);
-        for y in 0..32 {
-            for x in 0..64 {
-                assert!(!rip8.get_display_spot(x, y));
-            }
-        }
-        assert_eq!(rip8.v[0xf], 1);
+    #[test]
+    fn test_cycle_count() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0x70, 0x01, // v0 += 1
+            0xf1, 0x0a, // v1 = key
+            0x12, 0x02, // jp add
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.get_cycle_count(), 0);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.get_cycle_count(), 2);
+
+        // Stalled steps don't count
+        rip8.run_cycles(10);
+        assert_eq!(rip8.get_cycle_count(), 3);
+        rip8.set_keydown(0x5, true);
+        rip8.set_keydown(0x5, false);
+        rip8.run_cycles(3);
+        assert_eq!(rip8.get_cycle_count(), 6);
+        assert_eq!(rip8.get_register(0), 2);
+
+        rip8.reset();
+        assert_eq!(rip8.get_cycle_count(), 0);
+    }
+
+    #[test]
+    fn test_analyze_rom() {
+        let rom = [
+            0x60, 0x12, // LD V0, 0x12
+            0x8a, 0x34, // ADD VA, V3
+            0xd1, 0x25, // DRW V1, V2, 5
+            0x12, 0x00, // JP 0x200
+        ];
+        let report = analyze_rom(&rom, RIP8_ROM_START);
+        assert_eq!(report.instruction_count, 4);
+        assert!(report.unknown_opcodes.is_empty());
+        assert!(!report.uses_s_chip);
+        assert!(!report.uses_xo_chip);
+        assert_eq!(report.highest_register, Some(0xa));
+
+        let rom = [
+            0x00, 0xfd,             // EXIT
+            0xf0, 0x00, 0x12, 0x34, // LD I, 0x1234
+            0x81, 0x28,             // unknown
+            0x0f, 0xff,             // machine code routine, unsupported
+            0xff,                   // lone trailing byte
+        ];
+        let report = analyze_rom(&rom, 0x300);
+        assert_eq!(report.instruction_count, 4);
+        assert!(report.uses_s_chip);
+        assert!(report.uses_xo_chip);
+        assert_eq!(report.unknown_opcodes, vec![(0x306, 0x8128), (0x308, 0x0fff)]);
+        assert_eq!(report.highest_register, None);
+
+        assert_eq!(analyze_rom(&[], RIP8_ROM_START), RomReport::default());
     }
 
     #[test]
-    fn test_draw_stripes_offset() {
-        let mut rom = vec![0x61, 0x01, 0xd1, 0x18, 0x00, 0x00];
-        let sprite = vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
-        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+    fn test_seeded_random_source() {
+        use rand::{Rng, SeedableRng};
+        use rand::rngs::StdRng;
 
-        let rip8 = run_rom(&rom);
+        let rom = vec![
+            0xc0, 0xff, // v0 = rand
+            0xc1, 0xff, // v1 = rand
+            0xc2, 0x0f, // v2 = rand & 0xf
+            0x00, 0xfd, // exit
+        ];
+        let seeded = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            move || -> u8 { rng.gen::<u8>() }
+        };
+
+        let mut first = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, seeded(1234));
+        let mut second = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, seeded(1234));
+        assert_eq!(first.run_cycles(10), StepOutcome::Exited);
+        assert_eq!(second.run_cycles(10), StepOutcome::Exited);
+        assert_eq!(first.v, second.v);
+
+        // Plain functions still work too
+        let mut rip8 = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, ALWAYS_42).with_random(ALWAYS_ZERO);
+        rip8.run_cycles(10);
+        assert_eq!(rip8.v[0..3], [0x00, 0x00, 0x00]);
+    }
 
-        assert_eq!(rip8.i, stop_address);
-        assert_eq!(rip8.pc, stop_address);
-        for y in 0..32 {
-            for x in 0..64 {
-                if x > 0 && x < 9 && y > 0 && y < 9 && x % 2 == y % 2 {
-                    assert!(rip8.get_display_spot(x, y));
-                } else {
-                    assert!(!rip8.get_display_spot(x, y));
-                }
-            }
-        }
+    #[test]
+    fn test_bcd_wraps_at_end_of_memory() {
+        let rom = vec![
+            0xaf, 0xfe, // i = 0xffe
+            0x60, 0x7b, // v0 = 123
+            0xf0, 0x33, // i[0..3] = bcd(v0)
+            0xf2, 0x65, // v0..v2 = i[0..3]
+            0x00, 0xfd, // exit
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_until_halt(100), StepOutcome::Exited);
+        assert_eq!(rip8.read_memory(0xffe), 1);
+        assert_eq!(rip8.read_memory(0xfff), 2);
+        assert_eq!(rip8.read_memory(0x000), 3);
+        assert_eq!(rip8.v[0..3], [1, 2, 3]);
     }
 
     #[test]
-    fn test_draw_wrapped() {
-        let mut rom = vec![0x61, 0x39, 0x62, 0x19, 0xd1, 0x28, 0x00, 0x00];
-        let sprite = vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
-        let stop_address = append_trailing_data_to_rom(&mut rom, sprite);
+    fn test_jump_to_end_of_memory() {
+        // The second byte of the instruction at 0xfff is fetched from 0x000,
+        // the first byte of the font ("0" starts with 0xf0)
+        let rom = vec![0x1f, 0xff];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.write_memory(0xfff, 0x12);
+        assert_eq!(rip8.run_cycles(2), StepOutcome::Continue);
+        assert_eq!(rip8.get_pc(), 0x2f0);
 
-        assert_eq!(rip8.i, stop_address);
-        assert_eq!(rip8.pc, stop_address);
-        for y in 0..32 {
-            for x in 0..64 {
-                if (x == 0 && y ==  0) ||
-                    (y > 24 && x > 56) ||
-                    (y > 24 && x == 0) ||
-                    (y ==0 && x > 56) {
-                    assert!(rip8.get_display_spot(x, y));
-                } else {
-                    assert!(!rip8.get_display_spot(x, y));
-                }
-            }
-        }
+        // Whatever is at the end of memory, stepping from there never panics
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_cycles(2), StepOutcome::IllegalInstruction(0xfff0));
     }
 
     #[test]
-    fn test_skp_taken() {
-        let rom = vec![0x63, 0x01, 0xe3, 0x9e, 0x00, 0x00];
+    fn test_self_modifying_code() {
+        let rom = vec![
+            0xa2, 0x12, // i = 0x212
+            0x60, 0x65, // v0 = 0x65
+            0x61, 0x02, // v1 = 0x02
+            0x22, 0x12, // call 0x212
+            0x83, 0x50, // v3 = v5
+            0xf1, 0x55, // ld [i], v1 (turns 0x212 into "v5 = 0x02")
+            0x22, 0x12, // call 0x212
+            0x00, 0xfd, // exit
+            0x00, 0x00,
+            0x65, 0x01, // v5 = 0x01
+            0x00, 0xee, // ret
+        ];
 
         let mut rip8 = rip8_with_rom(&rom);
-        rip8.set_keydown(1, true);
-        run(&mut rip8);
+        assert_eq!(rip8.run_cycles(100), StepOutcome::Exited);
+        assert_eq!(rip8.v[3], 0x01);
+        assert_eq!(rip8.v[5], 0x02);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16 + 2);
+        // Writes from outside count too, even if they only touch one of
+        // the instruction's bytes
+        let rom = vec![
+            0x75, 0x01, // v5 += 0x01
+            0x12, 0x00, // jp 0x200
+        ];
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_register(5, 0x00);
+        rip8.run_cycles(4);
+        assert_eq!(rip8.v[5], 0x02);
+        rip8.write_memory(0x201, 0x10);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.v[5], 0x12);
     }
 
     #[test]
-    fn test_skp_not_taken() {
-        let rom = vec![0x63, 0x01, 0xe3, 0x9e, 0x00, 0x00];
+    fn test_decode() {
+        assert_eq!(decode(0x00e0), Instruction::Cls);
+        assert_eq!(decode(0x6a12), Instruction::LdVxKk(0xa, 0x12));
+        assert_eq!(decode(0xd125), Instruction::Drw(0x1, 0x2, 0x5));
+        assert_eq!(decode(0xb345), Instruction::JpV0(0x345));
+        assert_eq!(decode(0xf301), Instruction::Plane(0x3));
+        assert_eq!(decode(0xf000), Instruction::LdILong);
+
+        // Any other 0nnn is SYS
+        assert_eq!(decode(0x0123), Instruction::Sys(0x123));
+        assert_eq!(decode(0x00e1), Instruction::Sys(0x0e1));
+
+        // Close to valid opcodes, but not quite
+        for ir in [0x0000, 0x00c1, 0x00fb, 0x5121, 0x8128, 0x9121, 0xe19f, 0xf100, 0xf0ff] {
+            assert_eq!(decode(ir), Instruction::Illegal(ir));
+        }
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0xf0, 0x29, // i = font(v0)
+            0xd0, 0x05, // draw i..i[5] at (v0, v0)
+            0x00, 0xfd, // exit
+        ];
 
         let rip8 = run_rom(&rom);
+        let ascii = rip8.to_ascii('#', '.');
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), RIP8_DISPLAY_HEIGHT);
+        assert!(lines.iter().all(|line| line.len() == RIP8_DISPLAY_WIDTH));
+        assert!(ascii.ends_with('\n'));
+        let glyph: Vec<&str> = lines[..6].iter().map(|line| &line[..6]).collect();
+        assert_eq!(glyph, vec![
+            "####..",
+            "#..#..",
+            "#..#..",
+            "#..#..",
+            "####..",
+            "......",
+        ]);
+        assert_eq!(ascii.matches('#').count(), 14);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+    #[test]
+    fn test_cancel_input_wait() {
+        let rom = vec![
+            0x63, 0x07, // v3 = 7
+            0xf3, 0x0a, // v3 = wait_key()
+            0x61, 0x01, // v1 = 1
+            0x00, 0xfd, // exit
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
+        assert!(rip8.is_awaiting_input());
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 4);
+
+        rip8.cancel_input_wait();
+        assert!(!rip8.is_awaiting_input());
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Exited);
+        assert_eq!(rip8.v[1], 0x01);
+        assert_eq!(rip8.v[3], 0x07);
     }
 
     #[test]
-    fn test_sknp_taken() {
-        let rom = vec![0x62, 0x05, 0xe2, 0xa1, 0x00, 0x00];
+    fn test_ld_input_simultaneous_release() {
+        let rom = vec![
+            0xf3, 0x0a, // v3 = wait_key()
+            0x00, 0xfd, // exit
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        // Key 9 is already down when the wait starts
+        rip8.set_keydown(0x9, true);
+        rip8.run_cycles(5);
+        rip8.set_keydown(0x4, true);
+        rip8.set_keydown(0x9, false);
+        rip8.set_keydown(0x4, false);
+        assert!(rip8.is_awaiting_input());
+
+        assert_eq!(rip8.run_cycles(5), StepOutcome::Exited);
+        assert_eq!(rip8.v[3], 0x4);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16 + 2);
+    #[test]
+    fn test_keyboard_state() {
+        let mut rip8 = rip8_with_rom(&vec![0x00, 0xfd]);
+        rip8.set_keydown(0x2, true);
+        rip8.set_keydown(0xc, true);
+        rip8.set_keydown(0xc, false);
+        rip8.set_keydown(0xe, true);
+
+        assert!(rip8.is_keydown(0x2));
+        assert!(!rip8.is_keydown(0xc));
+        assert!(!rip8.is_keydown(RIP8_KEY_COUNT));
+        let mut expected = [false; RIP8_KEY_COUNT];
+        expected[0x2] = true;
+        expected[0xe] = true;
+        assert_eq!(rip8.keyboard_state(), expected);
     }
 
     #[test]
-    fn test_sknp_not_taken() {
-        let rom = vec![0x62, 0x00, 0xe2, 0xa1, 0x00, 0x00];
+    fn test_timing() {
+        let rom = vec![
+            0x81, 0x20, // v1 = v2
+            0xd0, 0x15, // draw i..i[5] at (v0, v1)
+            0xf3, 0x0a, // v3 = wait_key()
+        ];
 
+        // By default every instruction takes a single cycle
         let mut rip8 = rip8_with_rom(&rom);
-        rip8.set_keydown(0, true);
-        run(&mut rip8);
+        rip8.step();
+        assert_eq!(rip8.get_last_step_cost(), 1);
+        rip8.step();
+        assert_eq!(rip8.get_last_step_cost(), 1);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_timing(Timing::cosmac_vip());
+        rip8.step();
+        let move_cost = rip8.get_last_step_cost();
+        rip8.step();
+        let draw_cost = rip8.get_last_step_cost();
+        assert!(draw_cost > move_cost);
+        assert_eq!(draw_cost, Timing::cosmac_vip().cost(Instruction::Drw(0, 1, 5)));
+
+        // Stalled steps are free
+        rip8.step();
+        rip8.step();
+        assert!(rip8.is_awaiting_input());
+        assert_eq!(rip8.get_last_step_cost(), 0);
     }
 
     #[test]
-    fn test_ld_reg_dt() {
-        let rom = vec![0x60, 0xff, 0xf0, 0x07, 0x00, 0x00];
+    fn test_timer_readback() {
+        let rom = vec![
+            0x60, 0x09, // v0 = 9
+            0xf0, 0x18, // st = v0
+            0x61, 0x03, // v1 = 3
+            0xf1, 0x15, // dt = v1
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.run_cycles(4);
+        assert_eq!(rip8.get_sound_timer(), 9);
+        assert_eq!(rip8.get_delay_timer(), 3);
+        assert!(rip8.is_tone_on());
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.v[0], rip8.dt);
+    #[test]
+    fn test_timer_fraction() {
+        let mut rip8 = rip8_with_rom(&vec![0x60, 0x09, 0xf0, 0x18]);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.get_timer_fraction(), 0.0);
+
+        rip8.tick_timers(0.25 / 60.0);
+        assert!((rip8.get_timer_fraction() - 0.25).abs() < 1e-9);
+        rip8.tick_timers(0.5 / 60.0);
+        assert!((rip8.get_timer_fraction() - 0.75).abs() < 1e-9);
+        assert_eq!(rip8.get_sound_timer(), 9);
+
+        // Crossing a tick wraps the fraction around
+        rip8.tick_timers(0.5 / 60.0);
+        assert!((rip8.get_timer_fraction() - 0.25).abs() < 1e-9);
+        assert_eq!(rip8.get_sound_timer(), 8);
     }
 
     #[test]
-    fn test_ld_input() {
-        let rom = vec![0xf0, 0x0a, 0xff, 0x0a, 0x00, 0x00];
+    fn test_count_collided_rows() {
+        let mut rom = vec![
+            0x00, 0xff, // hires
+            0x60, 0x00, // v0 = 0
+            0x61, 0x00, // v1 = 0
+            0xa2, 0x12, // i = sprite
+            0xd0, 0x14, // draw i..i[4] at (v0, v1)
+            0x61, 0x01, // v1 = 1
+            0xd0, 0x14, // draw i..i[4] at (v0, v1)
+            0x61, 0x3d, // v1 = 61
+            0xd0, 0x14, // draw i..i[4] at (v0, v1), the last row is clipped
+            0xff, 0xff, 0x18, 0x00, // sprite
+        ];
+        let quirks = Quirks { count_collided_rows: true, clip_sprites: true, ..Quirks::default() };
 
         let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(quirks);
+        rip8.run_cycles(7);
+        // The second sprite's first two rows land on lit spots, its third
+        // one lands on the first sprite's blank row
+        assert_eq!(rip8.v[0xf], 0x02);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.v[0xf], 0x01);
+
+        // Without the quirk it's just a flag
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { count_collided_rows: false, ..quirks });
+        rip8.run_cycles(7);
+        assert_eq!(rip8.v[0xf], 0x01);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.v[0xf], 0x00);
+
+        // And so it is in lores, quirk or not
+        rom[1] = 0xfe; // lores
+        rom[15] = 0x1d; // v1 = 29
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(quirks);
+        rip8.run_cycles(7);
+        assert_eq!(rip8.v[0xf], 0x01);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.v[0xf], 0x00);
+    }
 
-        // no matter how much we run, it should stop until it receives input
-        for _ in 0..50 {
-            rip8.step(1);
-        }
-        rip8.set_keydown(0xf, true);
-        rip8.step(1);
-        rip8.set_keydown(0xf, false);
-        for _ in 0..50 {
-            rip8.step(1);
-        }
-        rip8.set_keydown(0x0, true);
-        rip8.step(1);
-        rip8.set_keydown(0x0, false);
-        // finish running
-        run(&mut rip8);
+    #[test]
+    fn test_pc_wraps_at_end_of_memory() {
+        // 0xfff holds the first byte of "v0 = 0xf0", the second one is the
+        // start of the font at 0x000
+        let rom = vec![0x1f, 0xff];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.v[0x0], 0xf);
-        assert_eq!(rip8.v[0xf], 0x0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.write_memory(0xfff, 0x60);
+        rip8.step();
+        assert_eq!(rip8.get_pc(), 0xfff);
+        assert_eq!(rip8.step(), StepOutcome::Continue);
+        assert_eq!(rip8.v[0], 0xf0);
+        assert_eq!(rip8.get_pc(), 0x001);
+
+        // Skips wrap around too
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.write_memory(0xfff, 0x30);
+        rip8.set_register(0, 0xf0);
+        rip8.run_cycles(2);
+        assert_eq!(rip8.get_pc(), 0x003);
     }
 
     #[test]
-    fn test_ld_dt_reg() {
-        let rom = vec![0x61, 0x42, 0xf1, 0x15, 0x00, 0x00];
-
-        let rip8 = run_rom_with_random(&rom, ALWAYS_42);
+    fn test_builder_defaults() {
+        let mut rip8 = Rip8::builder().rom(&[0xc0, 0xff]).build().unwrap();
+
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START);
+        assert_eq!(rip8.get_memory_model(), MemoryModel::Chip8);
+        assert_eq!(rip8.memory.len(), RIP8_MEMORY_SIZE);
+        assert_eq!(rip8.get_quirks(), Quirks::default());
+        assert_eq!(rip8.get_timing(), Timing::default());
+        assert_eq!(&rip8.memory[..RIP8_FONT.len()], &RIP8_FONT[..]);
+        assert_eq!(rip8.read_memory(RIP8_ROM_START), 0xc0);
+        assert_eq!(rip8.read_memory(RIP8_ROM_START + 2), 0xff);
+
+        rip8.step();
+        assert_eq!(rip8.get_register(0), 0x00);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.dt, rip8.v[0x1]);
-        assert_eq!(rip8.v[0x1], 0x42);
+    #[test]
+    fn test_builder_combinations() {
+        let mut rip8 = Rip8::builder()
+            .rom(&[0xc0, 0xff])
+            .load_address(0x600)
+            .memory_model(MemoryModel::XoChip)
+            .quirks(Quirks::chip48())
+            .timing(Timing::cosmac_vip())
+            .random(|| -> u8 { 0x5a })
+            .build()
+            .unwrap();
+
+        assert_eq!(rip8.get_pc(), 0x600);
+        assert_eq!(rip8.get_memory_model(), MemoryModel::XoChip);
+        assert_eq!(rip8.memory.len(), RIP8_XO_CHIP_MEMORY_SIZE);
+        assert_eq!(rip8.get_quirks(), Quirks::chip48());
+        assert_eq!(rip8.get_timing(), Timing::cosmac_vip());
+        assert_eq!(rip8.read_memory(RIP8_ROM_START), 0xff);
+
+        rip8.step();
+        assert_eq!(rip8.get_register(0), 0x5a);
+
+        // Images carry their own memory model
+        let image = vec![0x00; RIP8_MEMORY_SIZE];
+        let rip8 = Rip8::builder().image(&image).memory_model(MemoryModel::XoChip).load_address(0x000).build().unwrap();
+        assert_eq!(rip8.get_pc(), 0x000);
+        assert_eq!(rip8.get_memory_model(), MemoryModel::Chip8);
+        assert_eq!(rip8.memory, image);
     }
 
     #[test]
-    fn test_ld_st_reg() {
-        let rom = vec![0x61, 0x42, 0xf1, 0x18, 0x00, 0x00];
+    fn test_builder_errors() {
+        let result = Rip8::builder().rom(&[0x00; 2]).load_address(0x100).build();
+        assert_eq!(result.err(), Some(Rip8Error::LoadAddressTooLow(0x100)));
 
-        let rip8 = run_rom(&rom);
+        let rom = vec![0x00; RIP8_MEMORY_SIZE - 0xf00 + 1];
+        let result = Rip8::builder().rom(&rom).load_address(0xf00).build();
+        assert_eq!(result.err(), Some(Rip8Error::RomTooLarge(rom.len())));
+        assert!(Rip8::builder().rom(&rom).load_address(0xf00).memory_model(MemoryModel::XoChip).build().is_ok());
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.st, rip8.v[0x1]);
-        assert_eq!(rip8.v[0x1], 0x42);
+        let result = Rip8::builder().image(&[0x00; 16]).build();
+        assert_eq!(result.err(), Some(Rip8Error::ImageWrongSize(16)));
     }
 
     #[test]
-    fn test_add_i_reg() {
-        let rom = vec![0x61, 0x32, 0xa1, 0x23, 0xf1, 0x1e, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_warn_uninitialized_reads() {
+        let rom = vec![
+            0x60, 0x05, // v0 = 5
+            0x80, 0x14, // v0 += v1, v1 was never written
+            0x81, 0x00, // v1 = v0
+            0x80, 0x14, // v0 += v1
+            0xf2, 0x55, // i[0..=2] = v0..v2, v2 was never written
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.v[0x1], 0x32);
-        assert_eq!(rip8.i, 0x155);
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let hook_events = events.clone();
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_warn_uninitialized_reads(true);
+        rip8.set_trace_hook(Box::new(move |event| hook_events.borrow_mut().push(event.uninitialized_reads)));
+        rip8.run_cycles(5);
+        assert_eq!(*events.borrow(), vec![0x0000, 0x0002, 0x0000, 0x0000, 0x0004]);
+
+        // Resetting forgets what was written
+        events.borrow_mut().clear();
+        rip8.reset();
+        rip8.run_cycles(2);
+        assert_eq!(*events.borrow(), vec![0x0000, 0x0002]);
+
+        // Nothing gets flagged unless asked to
+        events.borrow_mut().clear();
+        rip8.reset();
+        rip8.set_warn_uninitialized_reads(false);
+        rip8.run_cycles(2);
+        assert_eq!(*events.borrow(), vec![0x0000, 0x0000]);
     }
 
     #[test]
-    fn test_ld_sprite_0() {
-        let rom = vec![0x60, 0x00, 0xf0, 0x29, 0x00, 0x00];
+    fn test_zero_registers() {
+        let rom = vec![0x60, 0x05];
+        let quirks = Quirks { zero_registers: true, ..Quirks::default() };
+
+        let mut rip8 = Rip8::builder().rom(&rom).quirks(quirks).build().unwrap();
+        assert_eq!(rip8.v, [0x00; 16]);
+        assert_eq!(rip8.i, 0x00);
+        rip8.step();
+        rip8.reset();
+        assert_eq!(rip8.v, [0x00; 16]);
+
+        let rip8 = Rip8::builder().rom(&rom).build().unwrap();
+        assert_eq!(rip8.v, [0xff; 16]);
+        assert_eq!(rip8.i, 0xff);
+    }
 
-        let rip8 = run_rom(&rom);
+    #[test]
+    fn test_parse_symbols() {
+        let text = "# generated by the assembler\n0x200 main\n\n  210   draw_loop  \nF00 sprite\n";
+        let symbols = parse_symbols(text).unwrap();
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.get(&0x200).map(String::as_str), Some("main"));
+        assert_eq!(symbols.get(&0x210).map(String::as_str), Some("draw_loop"));
+        assert_eq!(symbols.get(&0xf00).map(String::as_str), Some("sprite"));
+
+        assert_eq!(parse_symbols("0x200 main\n0x210\n"), Err(Rip8Error::SymbolsMalformed(2)));
+        assert_eq!(parse_symbols("0x200 main loop\n"), Err(Rip8Error::SymbolsMalformed(1)));
+        assert_eq!(parse_symbols("main 0x200\n"), Err(Rip8Error::SymbolsMalformed(1)));
+        assert_eq!(parse_symbols("0x10000 main\n"), Err(Rip8Error::SymbolsMalformed(1)));
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+    #[test]
+    fn test_disassemble_range_with_symbols() {
+        let mut memory = vec![0x00; 0x200];
+        memory.extend_from_slice(&[0x22, 0x04, 0x12, 0x00, 0xa2, 0x08, 0x00, 0xee, 0xf0, 0x00, 0x03, 0x00]);
+        let symbols = parse_symbols("0x200 main\n0x204 draw_loop\n0x208 sprite\n").unwrap();
+
+        assert_eq!(disassemble_range_with_symbols(&memory, 0x200, 5, &symbols), vec![
+            (0x200, "main: CALL draw_loop".to_string()),
+            (0x202, "JP main".to_string()),
+            (0x204, "draw_loop: LD I, sprite".to_string()),
+            (0x206, "RET".to_string()),
+            (0x208, "sprite: LD I, 0x0300".to_string()),
+        ]);
+
+        assert_eq!(disassemble_with_symbols(0x2204, &symbols), "CALL draw_loop");
+        assert_eq!(disassemble_with_symbols(0xb200, &symbols), "JP V0, main");
+        assert_eq!(disassemble_with_symbols(0x1206, &symbols), "JP 0x206");
+        assert_eq!(disassemble_range(&memory, 0x200, 1), vec![(0x200, "CALL 0x204".to_string())]);
     }
 
     #[test]
-    fn test_ld_sprite_1() {
-        let rom = vec![0x60, 0x01, 0xf0, 0x29, 0x00, 0x00];
+    fn test_watchpoint() {
+        let rom = vec![
+            0x60, 0x11, // v0 = 0x11
+            0x61, 0x22, // v1 = 0x22
+            0x62, 0x33, // v2 = 0x33
+            0xa3, 0x00, // i = 0x300
+            0xf2, 0x55, // i[0..=2] = v0..v2
+            0x62, 0xfe, // v2 = 0xfe
+            0xa3, 0x00, // i = 0x300
+            0xf2, 0x33, // i[0..=2] = bcd(v2)
+            0x12, 0x10, // loop
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.add_watchpoint(0x301);
+        assert_eq!(rip8.run_cycles(4), StepOutcome::Continue);
+        assert_eq!(rip8.step(), StepOutcome::WatchpointHit { addr: 0x301, old: 0xff, new: 0x22 });
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0x20);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x60);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x20);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x20);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x70);
+        // The store went through entirely before breaking
+        assert_eq!(rip8.pc, RIP8_ROM_START + 0xa);
+        assert_eq!(rip8.memory_slice(0x300, 3), &[0x11, 0x22, 0x33]);
+
+        assert_eq!(rip8.run_cycles(2), StepOutcome::Continue);
+        assert_eq!(rip8.step(), StepOutcome::WatchpointHit { addr: 0x301, old: 0x22, new: 0x05 });
+        assert_eq!(rip8.memory_slice(0x300, 3), &[0x02, 0x05, 0x04]);
+
+        rip8.remove_watchpoint(0x301);
+        rip8.reset();
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
     }
 
     #[test]
-    fn test_ld_sprite_2() {
-        let rom = vec![0x60, 0x02, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_watch_register() {
+        let rom = vec![
+            0x60, 0x01, // v0 = 1
+            0x70, 0x05, // v0 += 5
+            0x70, 0x00, // v0 += 0
+            0x61, 0xff, // v1 = 0xff
+            0x80, 0x14, // v0 += v1, carrying into vf
+            0x12, 0x0a, // loop
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.watch_register(0);
+        assert_eq!(rip8.step(), StepOutcome::RegisterChanged { reg: 0, old: 0xff, new: 0x01 });
+        assert_eq!(rip8.step(), StepOutcome::RegisterChanged { reg: 0, old: 0x01, new: 0x06 });
+
+        // Writing the same value back isn't a change
+        assert_eq!(rip8.step(), StepOutcome::Continue);
+        assert_eq!(rip8.step(), StepOutcome::Continue);
+
+        rip8.unwatch_register(0);
+        rip8.watch_register(0xf);
+        assert_eq!(rip8.step(), StepOutcome::RegisterChanged { reg: 0xf, old: 0xff, new: 0x01 });
+        assert_eq!(rip8.v[0], 0x05);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
     }
 
     #[test]
-    fn test_ld_sprite_3() {
-        let rom = vec![0x60, 0x03, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_display_hash() {
+        let rom = vec![
+            0xa2, 0x0a, // i = sprite
+            0xd0, 0x15, // draw i..i[5] at (v0, v1)
+            0x00, 0xe0, // clear
+            0xd0, 0x15, // draw i..i[5] at (v0, v1)
+            0x12, 0x08, // loop
+            0xf0, 0x90, 0xf0, 0x90, 0xf0, // sprite
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        let blank = rip8.display_hash();
+        rip8.run_cycles(2);
+        let drawn = rip8.display_hash();
+        assert_ne!(drawn, blank);
+        rip8.step();
+        assert_eq!(rip8.display_hash(), blank);
+        rip8.step();
+        assert_eq!(rip8.display_hash(), drawn);
+
+        // Lighting a spot on the second plane alone changes it too
+        rip8.display[RIP8_HIRES_DISPLAY_SIZE] = 0x80;
+        assert_ne!(rip8.display_hash(), drawn);
     }
 
     #[test]
-    fn test_ld_sprite_4() {
-        let rom = vec![0x60, 0x04, 0xf0, 0x29, 0x00, 0x00];
+    fn test_sys() {
+        let rom = vec![
+            0x01, 0x23, // sys 0x123
+            0x60, 0x05, // v0 = 5
+            0x00, 0x00, // halt
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.run_cycles(3), StepOutcome::IllegalInstruction(0x0000));
+        assert_eq!(rip8.v[0], 0x05);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x10);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { trap_sys: true, ..Quirks::default() });
+        assert_eq!(rip8.step(), StepOutcome::IllegalInstruction(0x0123));
+        assert_eq!(rip8.v[0], 0xff);
     }
 
     #[test]
-    fn test_ld_sprite_5() {
-        let rom = vec![0x60, 0x05, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_peek() {
+        let rom = vec![
+            0x60, 0x12, // v0 = 0x12
+            0xf0, 0x00, 0x12, 0x34, // i = 0x1234
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.dt = 0x10;
+        assert_eq!(rip8.peek_opcode(), 0x6012);
+        assert_eq!(rip8.peek_disassembly(), "LD V0, 0x12");
+        assert_eq!(rip8.peek_opcode(), 0x6012);
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START);
+        assert_eq!(rip8.get_register(0), 0xff);
+        assert_eq!(rip8.get_cycle_count(), 0);
+
+        rip8.step();
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 2);
+        assert_eq!(rip8.peek_opcode(), 0xf000);
+        assert_eq!(rip8.peek_disassembly(), "LD I, 0x1234");
+        assert_eq!(rip8.get_pc(), RIP8_ROM_START + 2);
+        assert_eq!(rip8.get_delay_timer(), 0x10);
     }
 
     #[test]
-    fn test_ld_sprite_6() {
-        let rom = vec![0x60, 0x06, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_timers_tick_while_awaiting_input() {
+        let rom = vec![
+            0x60, 0x3c, // v0 = 60
+            0xf0, 0x18, // st = v0
+            0xf1, 0x0a, // v1 = wait for key
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.run_cycles(3);
+        assert!(rip8.is_awaiting_input());
+        assert!(rip8.is_tone_on());
+
+        // A second of frames, stepping (and getting nowhere) in between
+        for _ in 0..60 {
+            rip8.tick_timers(1.0 / 60.0);
+            assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
+        }
+        assert!(rip8.is_awaiting_input());
+        assert_eq!(rip8.get_sound_timer(), 0);
+        assert!(!rip8.is_tone_on());
     }
 
     #[test]
-    fn test_ld_sprite_7() {
-        let rom = vec![0x60, 0x07, 0xf0, 0x29, 0x00, 0x00];
+    fn test_builder_start_address() {
+        let payload = [0x12, 0x34, 0x56];
+        let mut rip8 = Rip8::builder().rom(&payload).load_address(0x400).start_address(0x200).build().unwrap();
+        assert_eq!(rip8.get_pc(), 0x200);
+        assert_eq!(rip8.memory_slice(0x400, 3), &payload);
+        assert_eq!(rip8.read_memory(0x200), 0xff);
+
+        // Resetting goes back to the start address too
+        rip8.write_memory(0x200, 0x14);
+        rip8.write_memory(0x201, 0x00);
+        rip8.step();
+        assert_eq!(rip8.get_pc(), 0x400);
+        rip8.reset();
+        assert_eq!(rip8.get_pc(), 0x200);
+
+        let result = Rip8::builder().rom(&payload).load_address(0x400).start_address(0x1000).build();
+        assert_eq!(result.err(), Some(Rip8Error::StartAddressOutOfRange(0x1000)));
+        let result = Rip8::builder().rom(&payload).load_address(0x100).start_address(0x200).build();
+        assert_eq!(result.err(), Some(Rip8Error::LoadAddressTooLow(0x100)));
+        let rip8 = Rip8::builder().rom(&payload).memory_model(MemoryModel::XoChip).start_address(0x1000).build().unwrap();
+        assert_eq!(rip8.get_pc(), 0x1000);
+    }
 
-        let rip8 = run_rom(&rom);
+    #[test]
+    fn test_load_segment() {
+        let mut rip8 = rip8_with_rom(&vec![]);
+        rip8.load_segment(&[0xa4, 0x00, 0xf1, 0x65, 0x00, 0x00], 0x300).unwrap();
+        rip8.load_segment(&[0x12, 0x34], 0x400).unwrap();
+        assert_eq!(rip8.memory_slice(0x300, 6), &[0xa4, 0x00, 0xf1, 0x65, 0x00, 0x00]);
+        assert_eq!(rip8.memory_slice(0x400, 2), &[0x12, 0x34]);
+        assert_eq!(rip8.read_memory(0x306), 0xff);
+
+        // The code in one segment reads the data in the other
+        rip8.set_pc(0x300);
+        run(&mut rip8);
+        assert_eq!(rip8.v[0..2], [0x12, 0x34]);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x20);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x40);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x40);
+        assert_eq!(rip8.load_segment(&[0x00; 3], 0xffe), Err(Rip8Error::SegmentOutOfRange(0xffe, 3)));
+        assert_eq!(rip8.memory_slice(0xffe, 2), &[0xff, 0xff]);
+        assert!(rip8.load_segment(&[0x00; 2], 0xffe).is_ok());
+        assert_eq!(rip8.load_segment(&[0x00], 0x1000), Err(Rip8Error::SegmentOutOfRange(0x1000, 1)));
     }
 
     #[test]
-    fn test_ld_sprite_8() {
-        let rom = vec![0x60, 0x08, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_instruction_budget() {
+        let rom = vec![0x12, 0x00]; // jp to itself
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_instruction_budget(Some(1000));
+        let mut outcome = StepOutcome::Continue;
+        while outcome == StepOutcome::Continue {
+            outcome = rip8.step();
+        }
+        assert_eq!(outcome, StepOutcome::BudgetExceeded);
+        assert_eq!(rip8.get_cycle_count(), 1000);
+        assert_eq!(rip8.step(), StepOutcome::BudgetExceeded);
+        assert_eq!(rip8.get_cycle_count(), 1000);
+
+        // A new frame brings a new budget
+        rip8.tick_timers(1.0 / 60.0);
+        assert_eq!(rip8.run_cycles(2000), StepOutcome::BudgetExceeded);
+        assert_eq!(rip8.get_cycle_count(), 2000);
+
+        rip8.set_instruction_budget(None);
+        assert_eq!(rip8.run_cycles(2000), StepOutcome::Continue);
     }
 
     #[test]
-    fn test_ld_sprite_9() {
-        let rom = vec![0x60, 0x09, 0xf0, 0x29, 0x00, 0x00];
+    fn test_instruction_budget_while_running_until_halt() {
+        let rom = vec![0x12, 0x00]; // jp to itself
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_instruction_budget(Some(1000));
+        assert_eq!(rip8.run_until_halt(usize::MAX), StepOutcome::BudgetExceeded);
+        assert_eq!(rip8.get_cycle_count(), 1000);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x10);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        // Each call gets a budget of its own
+        assert_eq!(rip8.run_until_halt(usize::MAX), StepOutcome::BudgetExceeded);
+        assert_eq!(rip8.get_cycle_count(), 2000);
     }
 
     #[test]
-    fn test_ld_sprite_a() {
-        let rom = vec![0x60, 0x0a, 0xf0, 0x29, 0x00, 0x00];
+    fn test_call_stack() {
+        let rom = vec![
+            0x22, 0x04, // call 0x204
+            0x00, 0x00, // halt
+            0x22, 0x08, // call 0x208
+            0x00, 0xee, // ret
+            0x60, 0x01, // v0 = 1
+            0x00, 0xee, // ret
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.stack_depth(), 0);
+        assert!(rip8.call_stack().is_empty());
+        rip8.run_cycles(3);
+        assert_eq!(rip8.stack_depth(), 2);
+        assert_eq!(rip8.call_stack(), vec![0x202, 0x206]);
+        rip8.step();
+        assert_eq!(rip8.call_stack(), vec![0x202]);
+        rip8.step();
+        assert_eq!(rip8.stack_depth(), 0);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x90);
+    #[test]
+    fn test_opcode_histogram() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0x70, 0x01, // v0 += 1
+            0x30, 0x03, // skip if v0 == 3
+            0x12, 0x02, // jp 0x202
+            0x00, 0x00, // halt
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_profiling(true);
+        run(&mut rip8);
+        assert_eq!(rip8.opcode_histogram(), BTreeMap::from([
+            ("6xkk", 1),
+            ("7xkk", 3),
+            ("3xkk", 3),
+            ("1nnn", 2),
+            ("illegal", 1),
+        ]));
+
+        // Nothing is counted unless asked to
+        let mut rip8 = rip8_with_rom(&rom);
+        run(&mut rip8);
+        assert!(rip8.opcode_histogram().is_empty());
     }
 
     #[test]
-    fn test_ld_sprite_b() {
-        let rom = vec![0x60, 0x0b, 0xf0, 0x29, 0x00, 0x00];
+    fn test_i_overflow_sets_vf() {
+        let rom = vec![
+            0xaf, 0xfe, // i = 0xffe
+            0x60, 0x01, // v0 = 1
+            0xf0, 0x1e, // i += v0
+            0xf0, 0x1e, // i += v0, past 0xfff
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.run_cycles(3);
+        assert_eq!((rip8.i, rip8.v[0xf]), (0x0fff, 0xff));
+        rip8.step();
+        assert_eq!((rip8.i, rip8.v[0xf]), (0x1000, 0xff));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xe0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xe0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xe0);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { i_overflow_sets_vf: true, ..Quirks::default() });
+        rip8.run_cycles(3);
+        assert_eq!((rip8.i, rip8.v[0xf]), (0x0fff, 0x00));
+        rip8.step();
+        assert_eq!((rip8.i, rip8.v[0xf]), (0x1000, 0x01));
     }
 
     #[test]
-    fn test_ld_sprite_c() {
-        let rom = vec![0x60, 0x0c, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_resolution_changed() {
+        let rom = vec![
+            0x00, 0xe0, // clear
+            0x00, 0xff, // hires
+            0x00, 0xff, // hires, again
+            0x00, 0xfe, // lores
+            0x00, 0x00,
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let mut rip8 = rip8_with_rom(&rom);
+        assert!(!rip8.is_hires());
+        assert_eq!(rip8.framebuffer_dimensions(), (RIP8_DISPLAY_WIDTH, RIP8_DISPLAY_HEIGHT));
+        assert!(rip8.take_resolution_changed());
+        assert!(!rip8.take_resolution_changed());
+
+        rip8.step();
+        assert!(!rip8.take_resolution_changed());
+        rip8.step();
+        assert!(rip8.is_hires());
+        assert_eq!(rip8.framebuffer_dimensions(), (RIP8_HIRES_DISPLAY_WIDTH, RIP8_HIRES_DISPLAY_HEIGHT));
+        assert_eq!(rip8.framebuffer().len(), RIP8_HIRES_DISPLAY_SIZE);
+        assert!(rip8.take_resolution_changed());
+
+        // Staying in hires doesn't count as a change
+        rip8.step();
+        assert!(rip8.is_hires());
+        assert!(!rip8.take_resolution_changed());
+
+        rip8.step();
+        assert!(!rip8.is_hires());
+        assert_eq!(rip8.framebuffer_dimensions(), (RIP8_DISPLAY_WIDTH, RIP8_DISPLAY_HEIGHT));
+        assert_eq!(rip8.framebuffer().len(), RIP8_DISPLAY_SIZE);
+        assert!(rip8.take_resolution_changed());
     }
 
     #[test]
-    fn test_ld_sprite_d() {
-        let rom = vec![0x60, 0x0d, 0xf0, 0x29, 0x00, 0x00];
+    fn test_dump_image() {
+        let rom = vec![
+            0x60, 0xab, // v0 = 0xab
+            0xa2, 0x00, // i = 0x200
+            0xf0, 0x55, // i[0] = v0, overwriting the first instruction
+            0x00, 0x00, // halt
+        ];
 
         let rip8 = run_rom(&rom);
+        let image = rip8.dump_image();
+        assert_eq!(image.len(), RIP8_MEMORY_SIZE);
+        assert_eq!(image[0x200], 0xab);
+        assert_eq!(&image[..RIP8_FONT.len()], &RIP8_FONT[..]);
+
+        let restored = Rip8::from_image_at_start(&image, DEFAULT_FREQUENCY, rip8.get_pc(), ALWAYS_ZERO);
+        assert_eq!(restored.dump_image(), image);
+        assert_eq!(restored.get_pc(), rip8.get_pc());
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xe0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x90);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xe0);
+    // Only built by `cargo test --no-default-features`, where the core is
+    // `no_std`: the crate compiling at all is most of the check
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn test_no_std_core_runs_rom() {
+        let rom = vec![
+            0x60, 0x05, // v0 = 5
+            0xf0, 0x29, // i = digit sprite for v0
+            0xd0, 0x05, // draw it at (5, 5)
+            0x00, 0x00, // halt
+        ];
+
+        let mut rip8 = Rip8::builder().rom(&rom).build().unwrap();
+        run(&mut rip8);
+        assert!(rip8.get_display_spot(5, 5));
+        assert_eq!(rip8.v[0xf], 0);
     }
 
     #[test]
-    fn test_ld_sprite_e() {
-        let rom = vec![0x60, 0x0e, 0xf0, 0x29, 0x00, 0x00];
+    fn test_from_rom_array() {
+        // What `include_bytes!` hands out: a reference to a fixed-size array
+        static ROM: &[u8; 6] = &[
+            0x60, 0x2a, // v0 = 0x2a
+            0x81, 0x00, // v1 = v0
+            0x00, 0x00, // halt
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = Rip8::from_rom(ROM, DEFAULT_FREQUENCY, ALWAYS_ZERO);
+        run(&mut rip8);
+        assert_eq!(rip8.v[1], 0x2a);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0xf0);
+        let image = rip8.dump_image();
+        let restored = Rip8::from_image(&image[..], DEFAULT_FREQUENCY, ALWAYS_ZERO);
+        assert_eq!(&restored.dump_image()[0x200..0x206], &ROM[..]);
     }
 
     #[test]
-    fn test_ld_sprite_f() {
-        let rom = vec![0x60, 0x0f, 0xf0, 0x29, 0x00, 0x00];
-
-        let rip8 = run_rom(&rom);
+    fn test_step_until_draw() {
+        let rom = vec![
+            0x60, 0x05, // v0 = 5
+            0x61, 0x03, // v1 = 3
+            0xf1, 0x29, // i = digit sprite for v1
+            0x71, 0x01, // v1 += 1
+            0xd0, 0x05, // draw it at (5, 5)
+            0x62, 0x07, // v2 = 7
+            0x00, 0x00, // halt
+        ];
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.memory[rip8.i as usize + 0], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 1], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 2], 0xf0);
-        assert_eq!(rip8.memory[rip8.i as usize + 3], 0x80);
-        assert_eq!(rip8.memory[rip8.i as usize + 4], 0x80);
+        let mut rip8 = rip8_with_rom(&rom);
+        let blank = rip8.display_hash();
+        assert_eq!(rip8.step_until_draw(100), StepOutcome::Continue);
+        assert_eq!(rip8.get_pc(), 0x20a);
+        assert_eq!(rip8.get_cycle_count(), 5);
+        assert!(rip8.get_display_spot(5, 5));
+        let drawn = rip8.display_hash();
+        assert_ne!(drawn, blank);
+
+        // Nothing else draws, so it runs into the halt
+        assert_eq!(rip8.step_until_draw(100), StepOutcome::IllegalInstruction(0x0000));
+        assert_eq!(rip8.display_hash(), drawn);
+
+        // Running out of cycles isn't mistaken for a draw
+        let mut rip8 = rip8_with_rom(&vec![0x12, 0x00]);
+        assert_eq!(rip8.step_until_draw(100), StepOutcome::NoDraw);
+        assert_eq!(rip8.get_cycle_count(), 100);
     }
 
     #[test]
-    fn test_ld_bcd() {
+    fn test_save_load_range() {
         let rom = vec![
-            0x60, 0xc6, // v0 = 0xc6
-            0x61, 0x4c, // v1 = 0x4c
-            0x62, 0xfe, // v2 = 0xfe
-            0x63, 0x03, // v3 = 0x03
-            0x64, 0x03, // v4 = 0x03
-            0xa6, 0x00, // i = 0x300
-            0xf0, 0x33, // *i = bcd(v0) = 198
-            0xf4, 0x1e, // i += 3
-            0xf1, 0x33, // *i = bcd(v1) = 76
-            0xf4, 0x1e, // i += 3
-            0xf2, 0x33, // *i = bcd(v2) = 254
-            0xf4, 0x1e, // i += 3
-            0xf3, 0x33, // *i = bcd(v3) = 3
-            0xf4, 0x1e, // i += 3
-            0x00, 0x00
+            0x61, 0x11, // v1 = 0x11
+            0x62, 0x22, // v2 = 0x22
+            0x63, 0x33, // v3 = 0x33
+            0xa3, 0x00, // i = 0x300
+            0x51, 0x32, // save v1-v3
+            0xa3, 0x10, // i = 0x310
+            0x53, 0x12, // save v3-v1
+            0x00, 0x00, // halt
         ];
 
-        let rip8 = run_rom(&rom);
+        let run_xo_chip = |rom: &Vec<u8>| {
+            let mut rip8 = Rip8::builder().rom(rom).memory_model(MemoryModel::XoChip).build().unwrap();
+            run(&mut rip8);
+            rip8
+        };
+        let rip8 = run_xo_chip(&rom);
+        assert_eq!(&rip8.memory[0x300..0x303], &[0x11, 0x22, 0x33]);
+        assert_eq!(&rip8.memory[0x310..0x313], &[0x33, 0x22, 0x11]);
+        assert_eq!(rip8.i, 0x310);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.i, 0x60c);
-        assert_eq!(rip8.memory[rip8.i as usize - 01], 0x03);
-        assert_eq!(rip8.memory[rip8.i as usize - 02], 0x00);
-        assert_eq!(rip8.memory[rip8.i as usize - 03], 0x00);
+        let mut rom = vec![
+            0xa3, 0x00, // i = 0x300
+            0x51, 0x33, // load v1-v3
+            0x57, 0x53, // load v7-v5
+            0x00, 0x00, // halt
+        ];
+        rom.resize(0x100, 0x00);
+        rom.extend_from_slice(&[0xaa, 0xbb, 0xcc]);
 
-        assert_eq!(rip8.memory[rip8.i as usize - 04], 0x04);
-        assert_eq!(rip8.memory[rip8.i as usize - 05], 0x05);
-        assert_eq!(rip8.memory[rip8.i as usize - 06], 0x02);
+        let rip8 = run_xo_chip(&rom);
+        assert_eq!(&rip8.v[1..4], &[0xaa, 0xbb, 0xcc]);
+        assert_eq!(&rip8.v[5..8], &[0xcc, 0xbb, 0xaa]);
+        assert_eq!(rip8.i, 0x300);
 
-        assert_eq!(rip8.memory[rip8.i as usize - 07], 0x06);
-        assert_eq!(rip8.memory[rip8.i as usize - 08], 0x07);
-        assert_eq!(rip8.memory[rip8.i as usize - 09], 0x00);
+        // Plain CHIP-8 doesn't have them
+        let mut rip8 = rip8_with_rom(&rom);
+        assert_eq!(rip8.step(), StepOutcome::Continue);
+        assert_eq!(rip8.step(), StepOutcome::IllegalInstruction(0x5133));
+        assert_eq!(rip8.v[1], 0xff);
 
-        assert_eq!(rip8.memory[rip8.i as usize - 10], 0x08);
-        assert_eq!(rip8.memory[rip8.i as usize - 11], 0x09);
-        assert_eq!(rip8.memory[rip8.i as usize - 12], 0x01);
+        assert_eq!(disassemble(0x5132), "SAVE V1-V3");
+        assert_eq!(disassemble(0x5313), "LOAD V3-V1");
+        assert_eq!(decode(0x5124), Instruction::Illegal(0x5124));
     }
 
     #[test]
-    fn test_store_registers() {
+    fn test_builder_byte_order() {
         let rom = vec![
-            0x60, 0xff,
-            0x61, 0x88,
-            0x62, 0x44,
-            0x63, 0x00,
-            0xa6, 0x00,
-            0xf3, 0x55,
-            0x00, 0x00
+            0x60, 0x2a, // v0 = 0x2a
+            0xa3, 0x00, // i = 0x300
+            0xf0, 0x33, // bcd of v0 at i
+            0x00, 0x00, // halt
         ];
+        let image = Rip8::from_rom(&rom, DEFAULT_FREQUENCY, ALWAYS_ZERO).dump_image();
+        let mut swapped = image.clone();
+        swapped.chunks_exact_mut(2).for_each(|word| word.swap(0, 1));
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = Rip8::builder().image(&swapped).byte_order(ByteOrder::LittleEndian).build().unwrap();
+        assert_eq!(rip8.dump_image(), image);
+        run(&mut rip8);
+        assert_eq!(rip8.v[0], 0x2a);
+        assert_eq!(&rip8.memory[0x300..0x303], &[0, 4, 2]);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        assert_eq!(rip8.i, 0x600 + 3 + 1);
-        assert_eq!(rip8.memory[rip8.i as usize - 01], 0x00);
-        assert_eq!(rip8.memory[rip8.i as usize - 02], 0x44);
-        assert_eq!(rip8.memory[rip8.i as usize - 03], 0x88);
-        assert_eq!(rip8.memory[rip8.i as usize - 04], 0xff);
+        // Taken as big endian, the first word is 0x2a60, a call to 0xa60
+        let mut rip8 = Rip8::builder().image(&swapped).build().unwrap();
+        step_cycle(&mut rip8);
+        assert_eq!(rip8.get_pc(), 0xa60);
+
+        // ROMs aren't affected
+        let rip8 = Rip8::builder().rom(&rom).byte_order(ByteOrder::LittleEndian).build().unwrap();
+        assert_eq!(rip8.dump_image(), image);
     }
 
     #[test]
-    fn test_load_registers() {
+    fn test_draw_zero_rows() {
         let mut rom = vec![
-            0x64, 0xff,
-            0xf3, 0x65,
-            0x00, 0x00
+            0x60, 0x00, // v0 = 0
+            0xa3, 0x00, // i = 0x300
+            0xd0, 0x00, // draw a large sprite at (0, 0)
+            0xd0, 0x00, // and again, erasing it
+            0x00, 0x00, // halt
         ];
-        let trailer = vec![0x42, 0x43, 0x44, 0x45];
-        let stop_address = append_trailing_data_to_rom(&mut rom, trailer);
+        rom.resize(0x100, 0x00);
+        rom.extend_from_slice(&[0x80; 16]);
 
+        // CHIP-8 draws nothing
         let rip8 = run_rom(&rom);
+        assert_eq!(rip8.display_hash(), Rip8::from_rom(&rom, DEFAULT_FREQUENCY, ALWAYS_ZERO).display_hash());
+        assert_eq!(rip8.v[0xf], 0);
 
-        assert_eq!(rip8.pc, stop_address);
-        assert_eq!(rip8.i, stop_address + 4);
-        assert_eq!(rip8.v[0], 0x42);
-        assert_eq!(rip8.v[1], 0x43);
-        assert_eq!(rip8.v[2], 0x44);
-        assert_eq!(rip8.v[3], 0x45);
+        // S-CHIP in lores draws 8x16
+        let quirks = Quirks { large_sprites: true, ..Quirks::default() };
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(quirks);
+        rip8.run_cycles(3);
+        assert!((0..16).all(|y| rip8.get_display_spot(0, y)));
+        assert!(!rip8.get_display_spot(0, 16));
+        assert!(!rip8.get_display_spot(1, 0));
+        assert_eq!(rip8.v[0xf], 0);
+        run(&mut rip8);
+        assert!((0..16).all(|y| !rip8.get_display_spot(0, y)));
+        assert_eq!(rip8.v[0xf], 1);
+
+        // Clipped off the bottom, the rows that didn't fit don't count as
+        // collided in lores, even under `count_collided_rows`
+        let mut rom = rom.clone();
+        rom[1] = 24; // v0 = 24, the sprite starts 8 rows above the bottom
+        let rip8 = run_rom_with_quirks(&rom, Quirks { count_collided_rows: true, clip_sprites: true, ..quirks });
+        assert_eq!(rip8.v[0xf], 1);
     }
 
     #[test]
-    fn test_cls() {
-        let rom = vec![0x00, 0xe0, 0x00, 0x00];
+    fn test_memory_protection() {
+        let rom = vec![
+            0x60, 0x2a, // v0 = 0x2a
+            0xa1, 0x00, // i = 0x100
+            0xf0, 0x33, // bcd of v0 at i
+            0xa3, 0x00, // i = 0x300
+            0xf0, 0x33, // bcd of v0 at i
+            0x00, 0x00, // halt
+        ];
 
-        let rip8 = run_rom(&rom);
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_memory_protection(true);
+        let reserved = rip8.memory[0x100..0x103].to_vec();
+        assert_eq!(rip8.run_cycles(3), StepOutcome::ProtectionFault(0x100));
+        assert_eq!(&rip8.memory[0x100..0x103], &reserved[..]);
+        assert_eq!(rip8.get_pc(), 0x206);
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        for x in 0..64 {
-            for y in 0..32 {
-                assert!(!rip8.get_display_spot(x, y));
-            }
-        }
+        // Writes past the reserved region go through
+        assert_eq!(rip8.run_cycles(2), StepOutcome::Continue);
+        assert_eq!(&rip8.memory[0x300..0x303], &[0, 4, 2]);
+
+        // Off by default
+        let rip8 = run_rom(&rom);
+        assert_eq!(&rip8.memory[0x100..0x103], &[0, 4, 2]);
     }
 
     #[test]
-    fn test_draw_then_cls() {
+    fn test_or_draw() {
         let rom = vec![
             0x60, 0x00, // v0 = 0
-            0xf0, 0x29, // i = digits[v0]
-            0xd0, 0x05, // draw i..i[5] at (v0, v0)
-            0x00, 0xe0, // cls
-            0x00, 0x00
+            0x61, 0x08, // v1 = 8
+            0xf0, 0x29, // i = digit sprite for v0
+            0xd1, 0x15, // draw it at (8, 8)
+            0xf1, 0x29, // i = digit sprite for v1
+            0xd1, 0x15, // draw it over the first one
+            0x00, 0x00, // halt
         ];
 
+        // 0 and 8 share their outline, XOR leaves only 8's middle bar
         let rip8 = run_rom(&rom);
+        assert!(!rip8.get_display_spot(8, 8));
+        assert!(rip8.get_display_spot(9, 10));
+        assert!(!rip8.get_display_spot(8, 9));
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + rom.len() as u16);
-        for x in 0..64 {
-            for y in 0..32 {
-                assert!(!rip8.get_display_spot(x, y));
+        let mut rip8 = rip8_with_rom(&rom);
+        assert!(!rip8.get_or_draw());
+        rip8.set_or_draw(true);
+        assert!(rip8.get_or_draw());
+        run(&mut rip8);
+        assert!(rip8.get_display_spot(8, 8));
+        assert!(rip8.get_display_spot(9, 10));
+        assert!(rip8.get_display_spot(8, 9));
+        assert_eq!(rip8.v[0xf], 1);
+    }
+
+    #[test]
+    fn test_report_vblank_waits() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0xf0, 0x29, // i = digit sprite for v0
+            0xd0, 0x05, // draw it at (0, 0)
+            0x61, 0x08, // v1 = 8
+            0xd1, 0x05, // draw it at (8, 0)
+            0x12, 0x0a, // loop forever
+        ];
+        let cycles_per_frame = DEFAULT_FREQUENCY / 60;
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        rip8.set_report_vblank_waits(true);
+        let mut draw_frames = Vec::new();
+        for frame in 0..4 {
+            rip8.tick_timers(1.0 / 60.0);
+            for _ in 0..cycles_per_frame {
+                match rip8.step() {
+                    StepOutcome::Continue => (),
+                    StepOutcome::VblankWait => {
+                        draw_frames.push(frame);
+                        break;
+                    }
+                    outcome => panic!("unexpected {}", outcome),
+                }
             }
+            // Each draw is on screen by the end of its frame
+            assert_eq!(rip8.get_display_spot(8, 0), draw_frames.len() == 2);
         }
+        assert_eq!(draw_frames, vec![0, 1]);
+
+        // Less than a tick's worth of time doesn't end the wait
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        rip8.set_report_vblank_waits(true);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::VblankWait);
+        rip8.tick_timers(0.5 / 60.0);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
+        assert_eq!(rip8.get_pc(), 0x206);
+        rip8.tick_timers(0.5 / 60.0);
+        assert_eq!(rip8.run_cycles(10), StepOutcome::VblankWait);
+        assert_eq!(rip8.get_pc(), 0x20a);
+
+        // Not reported unless asked to
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.set_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        assert_eq!(rip8.run_cycles(10), StepOutcome::Continue);
+        assert!(rip8.is_awaiting_vblank());
     }
 
     #[test]
-    fn test_call_ret() {
-        let rom = vec![0x22, 0x04, 0x00, 0x00, 0x00, 0xee];
+    fn test_skip_long_load() {
+        let rom = vec![
+            0x60, 0x00, // v0 = 0
+            0x30, 0x00, // skip if v0 == 0
+            0xf0, 0x00, // i = 0x1234, skipped as a whole
+            0x12, 0x34,
+            0x61, 0x01, // v1 = 1
+            0x40, 0x00, // skip if v0 != 0, not taken
+            0xf0, 0x00, // i = 0x0456
+            0x04, 0x56,
+            0x00, 0x00, // halt
+        ];
 
         let rip8 = run_rom(&rom);
+        assert_eq!(rip8.v[1], 0x01);
+        assert_eq!(rip8.i, 0x0456);
+        assert_eq!(rip8.pc, RIP8_ROM_START + 18);
+    }
 
-        assert_eq!(rip8.pc, RIP8_ROM_START + 4);
-        assert_eq!(rip8.stack.len(), 0);
+    #[test]
+    fn test_key_release_survives_save_and_rewind() {
+        let rom = vec![
+            0xf0, 0x0a, // v0 = key
+            0x00, 0x00, // halt
+        ];
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.enable_delta_rewind(4);
+        step_cycle(&mut rip8);
+        assert!(rip8.is_awaiting_input());
+        rip8.set_keydown(7, true);
+        rip8.set_keydown(7, false);
+
+        // Released while waiting, the wait ends with the next step
+        let mut restored = Rip8::deserialize(&rip8.serialize(), ALWAYS_ZERO).unwrap();
+        step_cycle(&mut restored);
+        assert!(!restored.is_awaiting_input());
+        assert_eq!(restored.v[0], 7);
+
+        step_cycle(&mut rip8);
+        assert!(!rip8.is_awaiting_input());
+        assert!(rip8.step_back());
+        assert!(rip8.is_awaiting_input());
+        step_cycle(&mut rip8);
+        assert!(!rip8.is_awaiting_input());
+        assert_eq!(rip8.v[0], 7);
     }
 
     #[test]
-    fn test_dt_counts_down_at_60hz() {
-        let rom = vec![0x60, 0xff, 0xf0, 0x15, 0x12, 0x04];
+    fn test_delta_rewind_skips_stalled_steps() {
+        let rom = vec![
+            0x60, 0x05, // v0 = 5
+            0xf1, 0x0a, // v1 = key
+            0x00, 0x00, // halt
+        ];
 
         let mut rip8 = rip8_with_rom(&rom);
-        rip8.step(0);
-        rip8.step(0);
-        assert_eq!(rip8.dt, 0xff);
-        rip8.step(DEFAULT_FREQUENCY);
-        assert_eq!(rip8.dt, 0xc3);
+        rip8.enable_delta_rewind(2);
+        step_cycle(&mut rip8);
+        step_cycle(&mut rip8);
+        for _ in 0..8 {
+            step_cycle(&mut rip8);
+            assert!(rip8.is_awaiting_input());
+        }
+
+        // Waiting didn't push the first two steps out of the buffer
+        assert!(rip8.step_back());
+        assert!(!rip8.is_awaiting_input());
+        assert_eq!(rip8.pc, RIP8_ROM_START + 2);
+        assert!(rip8.step_back());
+        assert_eq!(rip8.pc, RIP8_ROM_START);
+        assert_eq!(rip8.v[0], 0xff);
+        assert!(!rip8.step_back());
+    }
+
+    #[test]
+    fn test_hires_draw() {
+        let mut rom = vec![
+            0x60, 0x64, // v0 = 100
+            0x61, 0x28, // v1 = 40
+            0xd0, 0x11, // draw i..i[1] at (v0, v1)
+            0x00, 0xff, // hires
+            0xd0, 0x11, // draw i..i[1] at (v0, v1)
+            0x00, 0x00,
+        ];
+        append_trailing_data_to_rom(&mut rom, vec![0x80]);
+
+        let mut rip8 = rip8_with_rom(&rom);
+        rip8.enable_delta_rewind(8);
+        rip8.run_cycles(4);
+        // Lores wraps the coordinates around 64x32
+        assert!(rip8.get_display_spot(36, 8));
+
+        rip8.run_cycles(2);
+        assert!(rip8.is_hires());
+        assert_eq!(rip8.lit_pixels(), vec![(100, 40)]);
+        let restored = Rip8::deserialize(&rip8.serialize(), ALWAYS_ZERO).unwrap();
+        assert!(restored.is_hires());
+        assert_eq!(restored.display_hash(), rip8.display_hash());
+
+        // Undoing the switch brings back the lores screen
+        rip8.take_resolution_changed();
+        assert!(rip8.step_back());
+        assert!(rip8.step_back());
+        assert!(!rip8.is_hires());
+        assert!(rip8.take_resolution_changed());
+        assert_eq!(rip8.lit_pixels(), vec![(36, 8)]);
     }
-}
 
+    #[test]
+    fn test_draw_zero_rows_hires() {
+        let mut rom = vec![
+            0x00, 0xff, // hires
+            0x60, 0x00, // v0 = 0
+            0xa3, 0x00, // i = 0x300
+            0xd0, 0x00, // draw a large sprite at (0, 0)
+            0x00, 0x00, // halt
+        ];
+        rom.resize(0x100, 0x00);
+        rom.extend_from_slice(&[0x80, 0x01].repeat(16));
+
+        // Hires draws 16x16, whether the lores quirk is set or not
+        for quirks in [Quirks::default(), Quirks { large_sprites: true, ..Quirks::default() }] {
+            let rip8 = run_rom_with_quirks(&rom, quirks);
+            assert!((0..16).all(|y| rip8.get_display_spot(0, y) && rip8.get_display_spot(15, y)));
+            assert!(!rip8.get_display_spot(0, 16));
+            assert!(!rip8.get_display_spot(16, 0));
+            assert_eq!(rip8.lit_pixels().len(), 32);
+        }
+
+        // Clipped off the bottom, the rows that didn't fit count as collided
+        // under `count_collided_rows`
+        let mut rom = rom.clone();
+        rom[3] = 56; // v0 = 56, the sprite starts 8 rows above the bottom
+        rom[8] = 0xd0; // and it's drawn again instead of halting
+        let rip8 = run_rom_with_quirks(&rom, Quirks { count_collided_rows: true, clip_sprites: true, ..Quirks::default() });
+        assert_eq!(rip8.v[0xf], 8 + 8);
+    }
+}
@@ -0,0 +1,71 @@
+// Runs test suites (such as corax+, BC_test or Timendus' quirks test) and
+// compares their final screen against a recorded hash. Third-party ROMs
+// can't be shipped, so this only builds with the `test-roms` feature and
+// reads them from the directory in RIP8_TEST_ROMS (tests/roms by default).
+// There, `golden.txt` lists one `FILE QUIRKS CYCLES HASH` line per ROM: the
+// file name, the quirk preset to run it with (default, vip, chip48 or schip),
+// how many instructions to run and `framebuffer_hash` of the screen (in hex)
+// once every test shows as passed. A failing ROM reports the hash it got,
+// which is also how new ones get recorded. Without golden.txt, or for ROMs
+// that aren't there, the test is skipped rather than failed
+#![cfg(feature = "test-roms")]
+
+use std::fs;
+use std::path::PathBuf;
+
+use rip8::{framebuffer_hash, run_rom_to_framebuffer, Quirks};
+
+fn roms_dir() -> PathBuf {
+    match std::env::var_os("RIP8_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms"),
+    }
+}
+
+fn quirks_preset(name: &str) -> Quirks {
+    match name {
+        "default" => Quirks::default(),
+        "vip" => Quirks::cosmac_vip(),
+        "chip48" => Quirks::chip48(),
+        "schip" => Quirks::schip(),
+        _ => panic!("unknown quirk preset '{}' in golden.txt", name),
+    }
+}
+
+#[test]
+fn test_roms_match_golden_hashes() {
+    let dir = roms_dir();
+    let golden = match fs::read_to_string(dir.join("golden.txt")) {
+        Ok(golden) => golden,
+        Err(e) => {
+            eprintln!("skipping, could not read {}: {}", dir.join("golden.txt").display(), e);
+            return;
+        }
+    };
+
+    let mut failed = Vec::new();
+    for line in golden.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (file, quirks, cycles, hash) = match fields[..] {
+            [file, quirks, cycles, hash] => (
+                file,
+                quirks_preset(quirks),
+                cycles.parse().unwrap(),
+                u64::from_str_radix(hash.trim_start_matches("0x"), 16).unwrap(),
+            ),
+            _ => panic!("malformed line in golden.txt: {}", line),
+        };
+        let rom = match fs::read(dir.join(file)) {
+            Ok(rom) => rom,
+            Err(e) => {
+                eprintln!("skipping {}, could not read it: {}", file, e);
+                continue;
+            }
+        };
+        let screen = framebuffer_hash(&run_rom_to_framebuffer(&rom, quirks, cycles).unwrap());
+        if screen != hash {
+            failed.push(format!("{}: screen hash is {:016x}, expected {:016x}", file, screen, hash));
+        }
+    }
+    assert!(failed.is_empty(), "{}", failed.join("\n"));
+}